@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::{self, FsError};
+use crate::reference_table;
+
+#[derive(Debug)]
+pub struct Archive {
+    entries: BTreeMap<i32, Cursor<Vec<u8>>>,
+}
+
+impl Archive {
+    pub fn new() -> Self {
+        Archive { entries: BTreeMap::new() }
+    }
+
+    pub fn decode(buffer: &mut Cursor<Vec<u8>>, entry: &reference_table::Entry) -> error::Result<Self> {
+        let mut ids = entry.entries().keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        let mut archive = Archive::new();
+        if ids.len() == 1 {
+            archive.entries.insert(ids[0], Cursor::new(buffer.get_ref().clone()));
+            return Ok(archive);
+        }
+
+        let length = buffer.get_ref().len();
+        if length == 0 {
+            return Err(FsError::Corrupt("Empty archive".to_string()));
+        }
+
+        buffer.set_position(length as u64 - 1);
+        let chunks = buffer.read_u8()? as usize;
+
+        let table_size = chunks * ids.len() * 4;
+        if table_size + 1 > length {
+            return Err(FsError::Corrupt("Invalid archive chunk table".to_string()));
+        }
+
+        let table_position = (length - 1 - table_size) as u64;
+        buffer.set_position(table_position);
+
+        let mut chunk_sizes = vec![vec![0usize; ids.len()]; chunks];
+        let mut sizes = vec![0usize; ids.len()];
+        for chunk in chunk_sizes.iter_mut() {
+            let mut chunk_size = 0i32;
+            for (i, size) in chunk.iter_mut().enumerate() {
+                chunk_size += buffer.read_i32::<BigEndian>()?;
+                if chunk_size < 0 {
+                    return Err(FsError::Corrupt("Negative archive chunk size".to_string()));
+                }
+
+                *size = chunk_size as usize;
+                sizes[i] += *size;
+            }
+        }
+
+        if sizes.iter().sum::<usize>() > table_position as usize {
+            return Err(FsError::Corrupt("Archive chunk sizes exceed data".to_string()));
+        }
+
+        let mut files = sizes.iter().map(|size| Vec::with_capacity(*size)).collect::<Vec<_>>();
+        buffer.set_position(0);
+        for chunk in &chunk_sizes {
+            for (i, size) in chunk.iter().enumerate() {
+                let mut data = vec![0; *size];
+                buffer.read_exact(&mut data)?;
+                files[i].extend_from_slice(&data);
+            }
+        }
+
+        for (id, file) in ids.into_iter().zip(files) {
+            archive.entries.insert(id, Cursor::new(file));
+        }
+
+        Ok(archive)
+    }
+
+    pub fn encode(&self) -> error::Result<Cursor<Vec<u8>>> {
+        let mut buf = Vec::new();
+        if self.entries.len() == 1 {
+            let entry = self.entries.values().next().unwrap();
+            buf.write_all(entry.get_ref())?;
+            return Ok(Cursor::new(buf));
+        }
+
+        for entry in self.entries.values() {
+            buf.write_all(entry.get_ref())?;
+        }
+
+        let mut previous = 0;
+        for entry in self.entries.values() {
+            let size = entry.get_ref().len() as i32;
+            buf.write_i32::<BigEndian>(size - previous)?;
+            previous = size;
+        }
+
+        buf.write_u8(1)?;
+        Ok(Cursor::new(buf))
+    }
+
+    pub fn get_entry(&self, id: i32) -> Option<&Cursor<Vec<u8>>> {
+        self.entries.get(&id)
+    }
+
+    pub fn entries(&self) -> &BTreeMap<i32, Cursor<Vec<u8>>> {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut BTreeMap<i32, Cursor<Vec<u8>>> {
+        &mut self.entries
+    }
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,51 +1,535 @@
-use std::io;
+use std::collections::HashMap;
+use std::io::{Cursor, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
 use bytes::Buf;
+use crate::archive::Archive;
 use crate::checksum_table::{ChecksumTable, Entry};
-use crate::container::Container;
+use crate::container::{self, Container};
+use crate::error::{self, FsError};
+use crate::diff::{CacheDiff, Change, FileDiff, GroupDiff};
 use crate::filestore::FileStore;
-use crate::{get_crc_checksum, get_whirlpool_digest};
-use crate::reference_table::ReferenceTable;
+use crate::lru::{LruCache, LruStats};
+use crate::store::Store;
+use crate::{get_crc_checksum, get_whirlpool_digest, hash_whirlpool, name_hash};
+use crate::reference_table::{self, ReferenceTable};
+use crate::verify::{Fault, Issue, VerifyReport};
+use crate::xtea::{KeyReport, XteaKeyStore};
+
+const MAPS_TYPE_ID: usize = 5;
+const DEFAULT_RAW_CAPACITY: usize = 32 * 1024 * 1024;
+const DEFAULT_CONTAINER_CAPACITY: usize = 64 * 1024 * 1024;
 
 #[derive(Debug)]
-pub struct Cache {
-    store: FileStore,
+pub struct Cache<S = FileStore> {
+    store: S,
+    raw_cache: Mutex<LruCache<(usize, usize), Vec<u8>>>,
+    container_cache: Mutex<LruCache<(usize, usize), Container>>,
+    checksum_table: Mutex<Option<Arc<ChecksumTable>>>,
+    subscribers: Mutex<Vec<Sender<Arc<ChecksumTable>>>>,
+}
+
+impl Cache<FileStore> {
+    pub fn create<P: AsRef<Path>>(root: P, index_count: usize) -> error::Result<Cache> {
+        Cache::create_with(FileStore::create(root, index_count)?)
+    }
+
+    pub fn repair(&mut self) -> error::Result<usize> {
+        self.invalidate_all();
+
+        let mut rebuilt = self.store.rebuild_index(255)?;
+        for type_id in 0..self.get_type_count() {
+            let mut max_id = self.store.get_file_count(type_id)?.saturating_sub(1);
+            if let Ok(table) = self.read_reference_table(type_id) {
+                max_id = max_id.max(table.entries().keys().map(|id| *id as usize).max().unwrap_or(0));
+            }
+
+            rebuilt += self.store.rebuild_index_with(type_id, max_id)?;
+        }
+
+        Ok(rebuilt)
+    }
 }
 
-impl Cache {
-    pub fn new(store: FileStore) -> Cache {
-        Cache { store }
+impl<S: Store> Cache<S> {
+    pub fn new(store: S) -> Cache<S> {
+        Cache::with_capacity(store, DEFAULT_RAW_CAPACITY, DEFAULT_CONTAINER_CAPACITY)
     }
 
-    pub fn create_checksum_table(&mut self) -> io::Result<ChecksumTable> {
+    pub fn with_capacity(store: S, raw_capacity: usize, container_capacity: usize) -> Cache<S> {
+        Cache {
+            store,
+            raw_cache: Mutex::new(LruCache::new(raw_capacity)),
+            container_cache: Mutex::new(LruCache::new(container_capacity)),
+            checksum_table: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn create_with(store: S) -> error::Result<Cache<S>> {
+        let mut cache = Cache::new(store);
+        for i in 0..cache.get_type_count() {
+            cache.write_reference_table(i, &ReferenceTable::new())?;
+        }
+
+        Ok(cache)
+    }
+
+    pub fn create_checksum_table(&self) -> error::Result<ChecksumTable> {
         let size = self.get_type_count();
-        let mut table = ChecksumTable::new(size as usize);
+        let mut table = ChecksumTable::new(size);
 
         for i in 0..size {
-            let mut buf = self.store.read(255, i)?;
+            table.entries_mut().insert(i, self.create_checksum_entry(i)?);
+        }
 
-            let mut crc = 0;
-            let mut version = 0;
-            let mut whirlpool = [0; 64];
+        Ok(table)
+    }
 
-            if buf.remaining() > 0 {
-                let ref_table = ReferenceTable::decode(Container::decode(&mut buf)?.data_mut())?;
-                crc = get_crc_checksum(&buf);
-                version = ref_table.version().unwrap_or_default();
-                buf.set_position(0);
-                whirlpool = get_whirlpool_digest(&buf);
-            }
+    fn create_checksum_entry(&self, type_id: usize) -> error::Result<Entry> {
+        let mut buf = self.read(255, type_id)?;
+
+        let mut crc = 0;
+        let mut version = 0;
+        let mut whirlpool = [0; 64];
 
-            table.entries_mut().insert(i, Entry::new(crc, version, whirlpool));
+        if buf.remaining() > 0 {
+            let ref_table = ReferenceTable::decode(Container::decode(&mut buf)?.data_mut())?;
+            crc = get_crc_checksum(&buf);
+            version = ref_table.version().unwrap_or_default();
+            buf.set_position(0);
+            whirlpool = get_whirlpool_digest(&buf);
         }
 
+        Ok(Entry::new(crc, version, whirlpool))
+    }
+
+    pub fn checksum_table(&self) -> error::Result<Arc<ChecksumTable>> {
+        let mut checksum_table = self.checksum_table.lock().expect("Failed to acquire lock");
+        if let Some(table) = checksum_table.as_ref() {
+            return Ok(Arc::clone(table));
+        }
+
+        let table = Arc::new(self.create_checksum_table()?);
+        *checksum_table = Some(Arc::clone(&table));
+
         Ok(table)
     }
 
-    pub fn store(&self) -> &FileStore {
+    pub fn subscribe(&self) -> Receiver<Arc<ChecksumTable>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().expect("Failed to acquire lock").push(sender);
+        receiver
+    }
+
+    fn update_checksum_table(&self, type_id: usize) -> error::Result<()> {
+        let table = {
+            let mut checksum_table = self.checksum_table.lock().expect("Failed to acquire lock");
+            let mut table = match checksum_table.as_ref() {
+                Some(table) => ChecksumTable::clone(table),
+                None if self.subscribers.lock().expect("Failed to acquire lock").is_empty() => return Ok(()),
+                None => self.create_checksum_table()?,
+            };
+
+            if type_id < table.entries().len() {
+                table.entries_mut()[type_id] = self.create_checksum_entry(type_id)?;
+            }
+
+            let table = Arc::new(table);
+            *checksum_table = Some(Arc::clone(&table));
+            table
+        };
+
+        self.subscribers.lock().expect("Failed to acquire lock").retain(|sender| sender.send(Arc::clone(&table)).is_ok());
+        Ok(())
+    }
+
+    pub fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        let key = (type_id, file_id);
+        if let Some(buf) = self.raw_cache.lock().expect("Failed to acquire lock").get(&key) {
+            return Ok(Cursor::new(buf.clone()));
+        }
+
+        let buf = self.store.read(type_id, file_id)?;
+        let weight = buf.get_ref().len();
+        self.raw_cache.lock().expect("Failed to acquire lock").insert(key, buf.get_ref().clone(), weight);
+
+        Ok(buf)
+    }
+
+    pub fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        self.invalidate(type_id, file_id);
+        self.store.write(type_id, file_id, data)?;
+
+        if type_id == 255 {
+            self.update_checksum_table(file_id)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn invalidate(&self, type_id: usize, file_id: usize) {
+        self.raw_cache.lock().expect("Failed to acquire lock").remove(&(type_id, file_id));
+        self.container_cache.lock().expect("Failed to acquire lock").remove(&(type_id, file_id));
+    }
+
+    pub fn invalidate_all(&self) {
+        self.raw_cache.lock().expect("Failed to acquire lock").clear();
+        self.container_cache.lock().expect("Failed to acquire lock").clear();
+        self.checksum_table.lock().expect("Failed to acquire lock").take();
+    }
+
+    pub fn raw_cache_stats(&self) -> LruStats {
+        self.raw_cache.lock().expect("Failed to acquire lock").stats()
+    }
+
+    pub fn container_cache_stats(&self) -> LruStats {
+        self.container_cache.lock().expect("Failed to acquire lock").stats()
+    }
+
+    pub fn read_container(&self, type_id: usize, file_id: usize) -> error::Result<Container> {
+        let key = (type_id, file_id);
+        if let Some(container) = self.container_cache.lock().expect("Failed to acquire lock").get(&key) {
+            return Ok(container.clone());
+        }
+
+        let container = Container::decode(&mut self.read(type_id, file_id)?)?;
+        let weight = container.data().get_ref().len();
+        self.container_cache.lock().expect("Failed to acquire lock").insert(key, container.clone(), weight);
+
+        Ok(container)
+    }
+
+    pub fn read_container_with_region_key(&self, type_id: usize, file_id: usize, region: u32, keys: &XteaKeyStore) -> error::Result<Container> {
+        let key = keys.get_key(region).unwrap_or(&container::NULL_KEY);
+        Container::decode_with_key(&mut self.read(type_id, file_id)?, key)
+    }
+
+    pub fn verify_region_keys(&self, keys: &XteaKeyStore) -> error::Result<KeyReport> {
+        let table = self.read_reference_table(MAPS_TYPE_ID)?;
+        let mut regions = keys.keys().keys().copied().collect::<Vec<_>>();
+        regions.sort_unstable();
+
+        let mut report = KeyReport::default();
+        for region in regions {
+            let name = format!("l{}_{}", region >> 8, region & 0xff);
+            let group_id = match table.find_entry(name_hash(&name)) {
+                Some(group_id) => group_id as usize,
+                None => {
+                    report.missing_mut().push(region);
+                    continue;
+                }
+            };
+
+            match self.read_container_with_region_key(MAPS_TYPE_ID, group_id, region, keys) {
+                Ok(_) => report.valid_mut().push(region),
+                Err(_) => report.invalid_mut().push(region),
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub fn verify(&self) -> error::Result<VerifyReport> {
+        self.verify_with_keys(&XteaKeyStore::new())
+    }
+
+    pub fn verify_with_keys(&self, keys: &XteaKeyStore) -> error::Result<VerifyReport> {
+        let landscapes = (0..=u16::MAX as u32)
+            .map(|region| (name_hash(&format!("l{}_{}", region >> 8, region & 0xff)), region))
+            .collect::<HashMap<_, _>>();
+
+        let mut report = VerifyReport::default();
+        let mut groups_checked = 0;
+
+        for type_id in 0..self.get_type_count() {
+            groups_checked += 1;
+
+            let mut buf = match self.store.read(255, type_id) {
+                Ok(buf) if buf.get_ref().is_empty() => {
+                    report.issues_mut().push(Issue::new(255, type_id, Fault::Missing));
+                    continue;
+                }
+                Ok(buf) => buf.into_inner(),
+                Err(e) => {
+                    report.issues_mut().push(Issue::new(255, type_id, Self::classify_fault(e)));
+                    continue;
+                }
+            };
+
+            match Container::encoded_length(&buf) {
+                Some(length) if length <= buf.len() => buf.truncate(length),
+                _ => {
+                    report.issues_mut().push(Issue::new(255, type_id, Fault::Truncated));
+                    continue;
+                }
+            }
+
+            let table = match Container::decode(&mut Cursor::new(buf)).and_then(|mut c| ReferenceTable::decode(c.data_mut())) {
+                Ok(table) => table,
+                Err(e) => {
+                    report.issues_mut().push(Issue::new(255, type_id, Fault::Undecodable(e.to_string())));
+                    continue;
+                }
+            };
+
+            let mut ids = table.entries().keys().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            for id in ids {
+                groups_checked += 1;
+
+                let entry = &table.entries()[&id];
+                let key = match type_id {
+                    MAPS_TYPE_ID => match entry.identifier().and_then(|identifier| landscapes.get(&identifier)) {
+                        Some(region) => keys.get_key(*region),
+                        None => Some(&container::NULL_KEY),
+                    },
+                    _ => Some(&container::NULL_KEY),
+                };
+
+                if let Some(fault) = self.verify_group(type_id, id as usize, entry, table.flags(), key) {
+                    report.issues_mut().push(Issue::new(type_id, id as usize, fault));
+                }
+            }
+        }
+
+        report.set_groups_checked(groups_checked);
+        Ok(report)
+    }
+
+    fn verify_group(&self, type_id: usize, group_id: usize, entry: &reference_table::Entry, flags: u8, key: Option<&[i32; 4]>) -> Option<Fault> {
+        let mut data = match self.store.read(type_id, group_id) {
+            Ok(buf) => buf.into_inner(),
+            Err(e) => return Some(Self::classify_fault(e)),
+        };
+
+        if data.is_empty() {
+            return Some(Fault::Missing);
+        }
+
+        match Container::encoded_length(&data) {
+            Some(length) if length <= data.len() => data.truncate(length),
+            _ => return Some(Fault::Truncated),
+        }
+
+        let data = Cursor::new(data);
+
+        let crc = get_crc_checksum(&data) as i32;
+        if crc != entry.crc() {
+            return Some(Fault::CrcMismatch { expected: entry.crc(), actual: crc });
+        }
+
+        if flags & reference_table::FLAG_WHIRLPOOL != 0 && hash_whirlpool(data.get_ref()) != *entry.whirlpool() {
+            return Some(Fault::WhirlpoolMismatch);
+        }
+
+        let key = key?;
+        match Container::decode_with_key(&mut data.clone(), key) {
+            Ok(_) => None,
+            Err(e) => Some(Fault::Undecodable(e.to_string())),
+        }
+    }
+
+    fn classify_fault(e: FsError) -> Fault {
+        match e {
+            e if e.is_not_found() => Fault::Missing,
+            FsError::Io(e) if e.kind() == ErrorKind::UnexpectedEof => Fault::Truncated,
+            _ => Fault::BrokenChain(e.to_string()),
+        }
+    }
+
+    pub fn compact<P: AsRef<Path>>(&self, dest: P) -> error::Result<(Cache, Vec<(usize, usize)>)> {
+        let type_count = self.get_type_count();
+        let mut cache = Cache::new(FileStore::create(dest, type_count)?);
+        let mut skipped = Vec::new();
+
+        for type_id in 0..type_count {
+            let mut ids = match self.read_reference_table(type_id) {
+                Ok(table) => table.entries().keys().map(|id| *id as usize).collect::<Vec<_>>(),
+                Err(_) => (0..self.get_file_count(type_id)?).collect(),
+            };
+            ids.sort_unstable();
+
+            for file_id in ids {
+                match self.store.read(type_id, file_id) {
+                    Ok(data) => cache.store.write(type_id, file_id, data.get_ref())?,
+                    Err(_) => skipped.push((type_id, file_id)),
+                }
+            }
+        }
+
+        for type_id in 0..type_count {
+            match self.store.read(255, type_id) {
+                Ok(data) => cache.store.write(255, type_id, data.get_ref())?,
+                Err(_) => skipped.push((255, type_id)),
+            }
+        }
+
+        Ok((cache, skipped))
+    }
+
+    pub fn diff<T: Store>(&self, other: &Cache<T>) -> error::Result<CacheDiff> {
+        self.diff_with(other, false)
+    }
+
+    pub fn diff_contents<T: Store>(&self, other: &Cache<T>) -> error::Result<CacheDiff> {
+        self.diff_with(other, true)
+    }
+
+    fn diff_with<T: Store>(&self, other: &Cache<T>, contents: bool) -> error::Result<CacheDiff> {
+        let mut diff = CacheDiff::default();
+
+        for type_id in 0..self.get_type_count().max(other.get_type_count()) {
+            let old = self.read_reference_table_or_empty(type_id)?;
+            let new = other.read_reference_table_or_empty(type_id)?;
+
+            let mut ids = old.entries().keys().chain(new.entries().keys()).copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            ids.dedup();
+
+            for id in ids {
+                let group = match (old.entries().get(&id), new.entries().get(&id)) {
+                    (Some(entry), None) => GroupDiff::new(type_id, id as usize, Change::Removed, Self::diff_all_children(entry, Change::Removed)),
+                    (None, Some(entry)) => GroupDiff::new(type_id, id as usize, Change::Added, Self::diff_all_children(entry, Change::Added)),
+                    (Some(old_entry), Some(new_entry)) => {
+                        let mut files = Self::diff_children(old_entry, new_entry);
+                        let mut changed = !files.is_empty()
+                            || old_entry.version() != new_entry.version()
+                            || old_entry.crc() != new_entry.crc()
+                            || old_entry.identifier() != new_entry.identifier();
+
+                        if contents {
+                            changed |= self.diff_group_contents(other, type_id, id as usize, old_entry, new_entry, &mut files);
+                        }
+
+                        if !changed {
+                            continue;
+                        }
+
+                        GroupDiff::new(type_id, id as usize, Change::Modified, files)
+                    }
+                    (None, None) => continue,
+                };
+
+                diff.groups_mut().push(group);
+            }
+        }
+
+        Ok(diff)
+    }
+
+    fn diff_all_children(entry: &reference_table::Entry, change: Change) -> Vec<FileDiff> {
+        let mut ids = entry.entries().keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.into_iter().map(|id| FileDiff::new(id as usize, change)).collect()
+    }
+
+    fn diff_children(old: &reference_table::Entry, new: &reference_table::Entry) -> Vec<FileDiff> {
+        let mut ids = old.entries().keys().chain(new.entries().keys()).copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+
+        ids.into_iter()
+            .filter_map(|id| match (old.entries().get(&id), new.entries().get(&id)) {
+                (Some(_), None) => Some(FileDiff::new(id as usize, Change::Removed)),
+                (None, Some(_)) => Some(FileDiff::new(id as usize, Change::Added)),
+                (Some(a), Some(b)) if a.identifier() != b.identifier() => Some(FileDiff::new(id as usize, Change::Modified)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn diff_group_contents<T: Store>(&self, other: &Cache<T>, type_id: usize, group_id: usize, old: &reference_table::Entry, new: &reference_table::Entry, files: &mut Vec<FileDiff>) -> bool {
+        let (mut old_container, mut new_container) = match (self.read_container(type_id, group_id), other.read_container(type_id, group_id)) {
+            (Ok(old_container), Ok(new_container)) => (old_container, new_container),
+            _ => return match (self.read(type_id, group_id), other.read(type_id, group_id)) {
+                (Ok(old_data), Ok(new_data)) => old_data.into_inner() != new_data.into_inner(),
+                _ => true,
+            },
+        };
+
+        if old_container.data().get_ref() == new_container.data().get_ref() {
+            return false;
+        }
+
+        let (old_archive, new_archive) = match (Archive::decode(old_container.data_mut(), old), Archive::decode(new_container.data_mut(), new)) {
+            (Ok(old_archive), Ok(new_archive)) => (old_archive, new_archive),
+            _ => return true,
+        };
+
+        for (id, data) in old_archive.entries() {
+            let modified = matches!(new_archive.get_entry(*id), Some(other) if other.get_ref() != data.get_ref());
+            if modified && !files.iter().any(|f| f.file_id() == *id as usize) {
+                files.push(FileDiff::new(*id as usize, Change::Modified));
+            }
+        }
+
+        files.sort_by_key(|f| f.file_id());
+        true
+    }
+
+    pub fn read_reference_table(&self, type_id: usize) -> error::Result<ReferenceTable> {
+        ReferenceTable::decode(self.read_container(255, type_id)?.data_mut())
+    }
+
+    fn read_reference_table_or_empty(&self, type_id: usize) -> error::Result<ReferenceTable> {
+        if type_id >= self.get_type_count() {
+            return Ok(ReferenceTable::new());
+        }
+
+        match self.read(255, type_id) {
+            Ok(buf) if buf.get_ref().is_empty() => Ok(ReferenceTable::new()),
+            Ok(mut buf) => ReferenceTable::decode(Container::decode(&mut buf)?.data_mut()),
+            Err(e) if e.is_not_found() => Ok(ReferenceTable::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn write_reference_table(&mut self, type_id: usize, table: &ReferenceTable) -> error::Result<()> {
+        let container = Container::new(container::COMPRESSION_GZIP, table.encode()?);
+        self.write(255, type_id, container.encode()?.get_ref())
+    }
+
+    pub fn read_file(&self, type_id: usize, group_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        let table = self.read_reference_table(type_id)?;
+        let entry = match table.entries().get(&(group_id as i32)) {
+            Some(entry) => entry,
+            None => return Err(FsError::GroupNotFound { type_id, group_id }),
+        };
+
+        let mut container = self.read_container(type_id, group_id)?;
+        let mut archive = Archive::decode(container.data_mut(), entry)?;
+
+        match archive.entries_mut().remove(&(file_id as i32)) {
+            Some(file) => Ok(file),
+            None => Err(FsError::FileNotFound { type_id, group_id, file_id }),
+        }
+    }
+
+    pub fn find_group(&self, type_id: usize, name: &str) -> error::Result<Option<usize>> {
+        let table = self.read_reference_table(type_id)?;
+        Ok(table.find_entry(name_hash(name)).map(|id| id as usize))
+    }
+
+    pub fn find_file(&self, type_id: usize, group_name: &str, file_name: &str) -> error::Result<Option<(usize, usize)>> {
+        let table = self.read_reference_table(type_id)?;
+        let group_id = match table.find_entry(name_hash(group_name)) {
+            Some(group_id) => group_id,
+            None => return Ok(None),
+        };
+
+        let file_id = table.entries()[&group_id].find_entry(name_hash(file_name));
+        Ok(file_id.map(|file_id| (group_id as usize, file_id as usize)))
+    }
+
+    pub fn store(&self) -> &S {
         &self.store
     }
 
-    pub fn store_mut(&mut self) -> &mut FileStore {
+    pub fn store_mut(&mut self) -> &mut S {
+        self.invalidate_all();
         &mut self.store
     }
 
@@ -53,7 +537,72 @@ impl Cache {
         self.store.get_type_count()
     }
 
-    pub fn get_file_count(&self, file_type: usize) -> io::Result<usize> {
+    pub fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
         self.store.get_file_count(file_type)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorystore::MemoryStore;
+
+    const KEY: [i32; 4] = [1, 2, 3, 4];
+
+    fn create_maps_cache() -> Cache<MemoryStore> {
+        let mut cache = Cache::create_with(MemoryStore::new(MAPS_TYPE_ID + 1)).unwrap();
+        let mut table = ReferenceTable::new();
+        table.set_flags(reference_table::FLAG_IDENTIFIERS);
+
+        let groups = [("m50_50", None), ("l50_50", Some(KEY)), ("l50_51", Some(KEY))];
+        for (group_id, (name, key)) in groups.into_iter().enumerate() {
+            let container = Container::new(container::COMPRESSION_GZIP, Cursor::new(vec![group_id as u8; 100]));
+            let data = container.encode_with_key(&key.unwrap_or(container::NULL_KEY)).unwrap();
+
+            let mut entry = reference_table::Entry::new();
+            entry.set_identifier(Some(name_hash(name)));
+            entry.set_crc(get_crc_checksum(&data) as i32);
+            table.entries_mut().insert(group_id as i32, entry);
+
+            cache.write(MAPS_TYPE_ID, group_id, data.get_ref()).unwrap();
+        }
+
+        cache.write_reference_table(MAPS_TYPE_ID, &table).unwrap();
+        cache
+    }
+
+    #[test]
+    fn verify_skips_landscapes_without_keys() {
+        let report = create_maps_cache().verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues());
+    }
+
+    #[test]
+    fn verify_decodes_landscapes_with_keys() {
+        let cache = create_maps_cache();
+
+        let mut keys = XteaKeyStore::new();
+        keys.insert((50 << 8) | 50, KEY);
+        let report = cache.verify_with_keys(&keys).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues());
+
+        keys.insert((50 << 8) | 51, [5, 6, 7, 8]);
+        let report = cache.verify_with_keys(&keys).unwrap();
+        let undecodable = report.undecodable().map(|issue| (issue.type_id(), issue.group_id())).collect::<Vec<_>>();
+        assert_eq!(undecodable, vec![(MAPS_TYPE_ID, 2)]);
+        assert_eq!(report.issues().len(), 1);
+    }
+
+    #[test]
+    fn diff_contents_reports_missing_groups_as_modified() {
+        let old = create_maps_cache();
+        let mut new = Cache::create_with(MemoryStore::new(MAPS_TYPE_ID + 2)).unwrap();
+        new.write_reference_table(MAPS_TYPE_ID, &old.read_reference_table(MAPS_TYPE_ID).unwrap()).unwrap();
+        new.write(MAPS_TYPE_ID, 0, old.read(MAPS_TYPE_ID, 0).unwrap().get_ref()).unwrap();
+        new.write(MAPS_TYPE_ID, 1, &[1, 2, 3]).unwrap();
+
+        let diff = old.diff_contents(&new).unwrap();
+        let groups = diff.groups().iter().map(|group| (group.type_id(), group.group_id(), group.change())).collect::<Vec<_>>();
+        assert_eq!(groups, vec![(MAPS_TYPE_ID, 1, Change::Modified), (MAPS_TYPE_ID, 2, Change::Modified)]);
+    }
+}
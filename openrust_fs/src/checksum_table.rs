@@ -1,9 +1,11 @@
-use std::io::{self, Cursor, Write};
-use byteorder::{BigEndian, WriteBytesExt};
+use std::io::{self, Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Buf;
 use num_bigint::BigUint;
+use crate::error::{self, FsError};
 use crate::{encrypt_rsa, hash_whirlpool};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChecksumTable {
     entries: Vec<Entry>,
 }
@@ -15,6 +17,50 @@ impl ChecksumTable {
         }
     }
 
+    pub fn decode(buffer: &mut Cursor<Vec<u8>>, whirlpool: bool, modulus: Option<BigUint>, public_key: Option<BigUint>) -> error::Result<Self> {
+        let size = if whirlpool {
+            buffer.read_u8()? as usize
+        } else {
+            buffer.remaining() / 8
+        };
+
+        let mut table = ChecksumTable::new(size);
+        for _ in 0..size {
+            let crc = buffer.read_u32::<BigEndian>()?;
+            let version = buffer.read_u32::<BigEndian>()? as i32;
+            let mut digest = [0; 64];
+            if whirlpool {
+                buffer.read_exact(&mut digest)?;
+            }
+
+            table.entries.push(Entry::new(crc, version, digest));
+        }
+
+        if whirlpool {
+            let position = buffer.position() as usize;
+            let mut expected = Vec::with_capacity(65);
+            expected.push(0);
+            expected.extend_from_slice(&hash_whirlpool(&buffer.get_ref()[..position].to_vec()));
+
+            let mut signature = Vec::new();
+            buffer.read_to_end(&mut signature)?;
+
+            let valid = match (modulus, public_key) {
+                (Some(modulus), Some(public_key)) => {
+                    let signature = BigUint::from_bytes_be(&signature);
+                    signature < modulus && signature.modpow(&public_key, &modulus) == BigUint::from_bytes_be(&expected)
+                }
+                _ => signature == expected,
+            };
+
+            if !valid {
+                return Err(FsError::InvalidSignature);
+            }
+        }
+
+        Ok(table)
+    }
+
     pub fn encode(&self) -> io::Result<Cursor<Vec<u8>>> {
         self.encode_impl(false, None, None)
     }
@@ -102,4 +148,63 @@ impl Entry {
     pub fn set_whirlpool(&mut self, whirlpool: [u8; 64]) {
         self.whirlpool = whirlpool;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsa::RsaKey;
+
+    fn create_table() -> ChecksumTable {
+        let mut table = ChecksumTable::new(3);
+        for i in 0..3u8 {
+            table.entries_mut().push(Entry::new(0x8000_0000 | i as u32, i as i32 * 10, [i; 64]));
+        }
+
+        table
+    }
+
+    fn assert_entries(table: &ChecksumTable, whirlpool: bool) {
+        assert_eq!(table.entries().len(), 3);
+        for (i, entry) in table.entries().iter().enumerate() {
+            assert_eq!(entry.crc(), 0x8000_0000 | i as u32);
+            assert_eq!(entry.version(), i as i32 * 10);
+            assert_eq!(*entry.whirlpool(), if whirlpool { [i as u8; 64] } else { [0; 64] });
+        }
+    }
+
+    #[test]
+    fn round_trip_without_whirlpool() {
+        let mut encoded = create_table().encode().unwrap();
+        assert_entries(&ChecksumTable::decode(&mut encoded, false, None, None).unwrap(), false);
+    }
+
+    #[test]
+    fn round_trip_without_key() {
+        let encoded = create_table().encode_impl(true, None, None).unwrap().into_inner();
+        assert_entries(&ChecksumTable::decode(&mut Cursor::new(encoded.clone()), true, None, None).unwrap(), true);
+
+        let mut tampered = encoded;
+        tampered[1] ^= 1;
+        assert!(matches!(ChecksumTable::decode(&mut Cursor::new(tampered), true, None, None), Err(FsError::InvalidSignature)));
+    }
+
+    #[test]
+    fn round_trip_with_key() {
+        let key = RsaKey::generate(768).unwrap();
+        let modulus = Some(key.modulus().clone());
+        let encoded = create_table().encode_impl(true, modulus.clone(), key.private_exponent().cloned()).unwrap().into_inner();
+
+        let table = ChecksumTable::decode(&mut Cursor::new(encoded.clone()), true, modulus.clone(), Some(key.public_exponent().clone())).unwrap();
+        assert_entries(&table, true);
+
+        assert!(matches!(ChecksumTable::decode(&mut Cursor::new(encoded.clone()), true, None, None), Err(FsError::InvalidSignature)));
+
+        let mut tampered = encoded;
+        tampered[1] ^= 1;
+        assert!(matches!(
+            ChecksumTable::decode(&mut Cursor::new(tampered), true, modulus, Some(key.public_exponent().clone())),
+            Err(FsError::InvalidSignature)
+        ));
+    }
+}
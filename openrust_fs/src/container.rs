@@ -1,16 +1,18 @@
-use std::io::{self, Cursor, Error, ErrorKind, Read};
+use std::io::{self, Cursor, Read};
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use crate::{bunzip2, bzip2, decipher_xtea, gunzip, gzip};
+use crate::error::{self, FsError};
+use crate::{bunzip2, bzip2, decipher_xtea, encipher_xtea, gunzip, gzip, lzma, unlzma};
 
-static NULL_KEY: [i32; 4] = [0; 4];
+pub static NULL_KEY: [i32; 4] = [0; 4];
 
 const DATA_OFFSET: usize = 5;
 pub const COMPRESSION_NONE: u8 = 0;
-const COMPRESSION_BZIP2: u8 = 1;
-const COMPRESSION_GZIP: u8 = 2;
+pub const COMPRESSION_BZIP2: u8 = 1;
+pub const COMPRESSION_GZIP: u8 = 2;
+pub const COMPRESSION_LZMA: u8 = 3;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Container {
     type_id: u8,
     data: Cursor<Vec<u8>>,
@@ -22,42 +24,65 @@ impl Container {
         Self { type_id, data, version: -1 }
     }
 
-    pub fn decode(buffer: &mut Cursor<Vec<u8>>) -> io::Result<Self> {
+    pub fn decode(buffer: &mut Cursor<Vec<u8>>) -> error::Result<Self> {
         Self::decode_with_key(buffer, &NULL_KEY)
     }
 
-    pub fn decode_with_key(mut buffer: &mut Cursor<Vec<u8>>, key: &[i32; 4]) -> io::Result<Self> {
+    pub fn decode_with_key(buffer: &mut Cursor<Vec<u8>>, key: &[i32; 4]) -> error::Result<Self> {
         let type_id = buffer.read_u8()?;
-        let length = buffer.read_i32::<BigEndian>()? as usize;
+        let length = buffer.read_i32::<BigEndian>()?;
+        if length < 0 {
+            return Err(FsError::Corrupt(format!("Invalid container length: {}", length)));
+        }
+
+        let length = length as usize;
 
-        if *key != [0i32, 0i32, 0i32, 0i32] {
+        if *key != NULL_KEY {
             let data_len = length + if type_id == COMPRESSION_NONE { DATA_OFFSET } else { DATA_OFFSET + 4 };
 
-            decipher_xtea(&mut buffer, DATA_OFFSET, data_len, key)?;
+            decipher_xtea(buffer, DATA_OFFSET, data_len, key)?;
         }
 
         if type_id == COMPRESSION_NONE {
-            let data = Cursor::new(buffer.clone()
-                .into_inner()
-                .split_off(DATA_OFFSET));
-            let version = Self::decode_version(buffer)?;
+            let mut data = vec![0; length];
+            buffer.read_exact(&mut data)?;
 
-            Ok(Self { type_id, data, version })
+            let version = Self::decode_version(buffer)?;
+            Ok(Self { type_id, data: Cursor::new(data), version })
         } else {
-            let uncompressed_length = buffer.get_i32() as usize;
+            let uncompressed_length = buffer.read_i32::<BigEndian>()?;
+            if uncompressed_length < 0 {
+                if *key != NULL_KEY {
+                    return Err(FsError::InvalidKey);
+                }
+
+                return Err(FsError::Corrupt(format!("Invalid uncompressed length: {}", uncompressed_length)));
+            }
+
+            let uncompressed_length = uncompressed_length as usize;
             let mut compressed_buf = vec![0; length];
             buffer.read_exact(&mut compressed_buf)?;
 
             let compressed_buf = Bytes::from(compressed_buf);
-            let decompress_fn: fn(&[u8]) -> io::Result<Vec<u8>> = match type_id {
-                COMPRESSION_BZIP2 => bunzip2,
-                COMPRESSION_GZIP => gunzip,
-                _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid compression type"))
+            let uncompressed = match type_id {
+                COMPRESSION_BZIP2 => bunzip2(&compressed_buf),
+                COMPRESSION_GZIP => gunzip(&compressed_buf),
+                COMPRESSION_LZMA => unlzma(&compressed_buf, uncompressed_length),
+                _ => return Err(FsError::InvalidCompression { compression: type_id }),
+            };
+
+            let uncompressed = match uncompressed {
+                Ok(uncompressed) => uncompressed,
+                Err(_) if *key != NULL_KEY => return Err(FsError::InvalidKey),
+                Err(e) => return Err(FsError::Corrupt(format!("Failed to decompress container: {}", e))),
             };
 
-            let uncompressed = decompress_fn(&compressed_buf)?;
             if uncompressed.len() != uncompressed_length {
-                return Err(Error::new(ErrorKind::InvalidData, "Length mismatch"));
+                if *key != NULL_KEY {
+                    return Err(FsError::InvalidKey);
+                }
+
+                return Err(FsError::LengthMismatch { expected: uncompressed_length, actual: uncompressed.len() });
             }
 
             let version = Self::decode_version(buffer)?;
@@ -65,7 +90,11 @@ impl Container {
         }
     }
 
-    pub fn encode(self) -> io::Result<Cursor<Vec<u8>>> {
+    pub fn encode(self) -> error::Result<Cursor<Vec<u8>>> {
+        self.encode_with_key(&NULL_KEY)
+    }
+
+    pub fn encode_with_key(self, key: &[i32; 4]) -> error::Result<Cursor<Vec<u8>>> {
         let remaining = self.data.remaining();
         let mut bytes = BytesMut::with_capacity(remaining);
         bytes.put(self.data);
@@ -74,7 +103,8 @@ impl Container {
             COMPRESSION_NONE => bytes.to_vec(),
             COMPRESSION_BZIP2 => bzip2(bytes.as_ref())?,
             COMPRESSION_GZIP => gzip(bytes.as_ref())?,
-            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid compression type")),
+            COMPRESSION_LZMA => lzma(bytes.as_ref())?,
+            _ => return Err(FsError::InvalidCompression { compression: self.type_id }),
         };
 
         let header = DATA_OFFSET + (if self.type_id == COMPRESSION_NONE { 0 } else { 4 }) + (if self.version != -1 { 2 } else { 0 });
@@ -93,7 +123,28 @@ impl Container {
             buf.put_u16(self.version as u16);
         }
 
-        Ok(Cursor::new(buf.to_vec()))
+        let mut buf = Cursor::new(buf.to_vec());
+        if *key != NULL_KEY {
+            let data_len = compressed.len() + if self.type_id == COMPRESSION_NONE { DATA_OFFSET } else { DATA_OFFSET + 4 };
+
+            encipher_xtea(&mut buf, DATA_OFFSET, data_len, key)?;
+        }
+
+        Ok(buf)
+    }
+
+    pub fn encoded_length(buffer: &[u8]) -> Option<usize> {
+        if buffer.len() < DATA_OFFSET {
+            return None;
+        }
+
+        let length = i32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]);
+        if length < 0 {
+            return None;
+        }
+
+        let header = if buffer[0] == COMPRESSION_NONE { DATA_OFFSET } else { DATA_OFFSET + 4 };
+        Some(header + length as usize)
     }
 
     fn decode_version(cursor: &mut Cursor<Vec<u8>>) -> io::Result<i16> {
@@ -123,4 +174,75 @@ impl Container {
     pub fn set_version(&mut self, version: i16) {
         self.version = version;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [i32; 4] = [0x1234_5678, -0x0bad_f00d, 0x7fff_ffff, 1];
+    const COMPRESSIONS: [u8; 4] = [COMPRESSION_NONE, COMPRESSION_BZIP2, COMPRESSION_GZIP, COMPRESSION_LZMA];
+
+    fn create_data() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip_all_compressions() {
+        for compression in COMPRESSIONS {
+            for version in [-1, 42] {
+                let mut container = Container::new(compression, Cursor::new(create_data()));
+                container.set_version(version);
+
+                let mut encoded = container.encode().unwrap();
+                let decoded = Container::decode(&mut encoded).unwrap();
+                assert_eq!(decoded.type_id(), compression);
+                assert_eq!(decoded.version(), version);
+                assert_eq!(decoded.data().get_ref(), &create_data());
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_all_compressions_with_key() {
+        for compression in COMPRESSIONS {
+            for version in [-1, 42] {
+                let mut container = Container::new(compression, Cursor::new(create_data()));
+                container.set_version(version);
+
+                let plain = container.clone().encode().unwrap().into_inner();
+                let encrypted = container.encode_with_key(&KEY).unwrap().into_inner();
+                assert_eq!(encrypted.len(), plain.len());
+                assert_ne!(encrypted, plain, "compression {}", compression);
+
+                let decoded = Container::decode_with_key(&mut Cursor::new(encrypted), &KEY).unwrap();
+                assert_eq!(decoded.type_id(), compression);
+                assert_eq!(decoded.version(), version);
+                assert_eq!(decoded.data().get_ref(), &create_data());
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_key_is_invalid_key() {
+        for compression in [COMPRESSION_BZIP2, COMPRESSION_GZIP, COMPRESSION_LZMA] {
+            let encrypted = Container::new(compression, Cursor::new(create_data())).encode_with_key(&KEY).unwrap().into_inner();
+
+            for seed in 1..64 {
+                let key = [seed, seed * 31, -seed, seed ^ 0x5555_5555];
+                match Container::decode_with_key(&mut Cursor::new(encrypted.clone()), &key) {
+                    Err(FsError::InvalidKey) => {}
+                    other => panic!("compression {} seed {}: {:?}", compression, seed, other.map(|c| c.data().get_ref().len())),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_is_error() {
+        let encoded = Container::new(COMPRESSION_GZIP, Cursor::new(create_data())).encode().unwrap().into_inner();
+        for length in 0..12 {
+            assert!(Container::decode(&mut Cursor::new(encoded[..length].to_vec())).is_err());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    file_id: usize,
+    change: Change,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupDiff {
+    type_id: usize,
+    group_id: usize,
+    change: Change,
+    files: Vec<FileDiff>,
+}
+
+#[derive(Debug, Default)]
+pub struct CacheDiff {
+    groups: Vec<GroupDiff>,
+}
+
+impl FileDiff {
+    pub fn new(file_id: usize, change: Change) -> Self {
+        FileDiff { file_id, change }
+    }
+
+    pub fn file_id(&self) -> usize {
+        self.file_id
+    }
+
+    pub fn change(&self) -> Change {
+        self.change
+    }
+}
+
+impl GroupDiff {
+    pub fn new(type_id: usize, group_id: usize, change: Change, files: Vec<FileDiff>) -> Self {
+        GroupDiff { type_id, group_id, change, files }
+    }
+
+    pub fn type_id(&self) -> usize {
+        self.type_id
+    }
+
+    pub fn group_id(&self) -> usize {
+        self.group_id
+    }
+
+    pub fn change(&self) -> Change {
+        self.change
+    }
+
+    pub fn files(&self) -> &Vec<FileDiff> {
+        &self.files
+    }
+}
+
+impl CacheDiff {
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn get_index(&self, type_id: usize) -> impl Iterator<Item = &GroupDiff> {
+        self.groups.iter().filter(move |g| g.type_id == type_id)
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &GroupDiff> {
+        self.groups.iter().filter(|g| g.change == Change::Added)
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &GroupDiff> {
+        self.groups.iter().filter(|g| g.change == Change::Removed)
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &GroupDiff> {
+        self.groups.iter().filter(|g| g.change == Change::Modified)
+    }
+
+    pub fn groups(&self) -> &Vec<GroupDiff> {
+        &self.groups
+    }
+
+    pub fn groups_mut(&mut self) -> &mut Vec<GroupDiff> {
+        &mut self.groups
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+pub type Result<T> = std::result::Result<T, FsError>;
+
+#[derive(Debug)]
+pub enum FsError {
+    Io(io::Error),
+    IndexNotFound { type_id: usize },
+    GroupNotFound { type_id: usize, group_id: usize },
+    FileNotFound { type_id: usize, group_id: usize, file_id: usize },
+    SectorMismatch { type_id: usize, group_id: usize, sector: u64, field: &'static str, expected: u32, actual: u32 },
+    BrokenChain { type_id: usize, group_id: usize, sector: u64 },
+    InvalidCompression { compression: u8 },
+    LengthMismatch { expected: usize, actual: usize },
+    InvalidKey,
+    InvalidSignature,
+    Corrupt(String),
+    InvalidInput(String),
+}
+
+impl FsError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, FsError::IndexNotFound { .. } | FsError::GroupNotFound { .. } | FsError::FileNotFound { .. })
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::Io(e) => write!(f, "{}", e),
+            FsError::IndexNotFound { type_id } => write!(f, "Index {} not found", type_id),
+            FsError::GroupNotFound { type_id, group_id } => write!(f, "Group {} not found in index {}", group_id, type_id),
+            FsError::FileNotFound { type_id, group_id, file_id } => {
+                write!(f, "File {} not found in group {} of index {}", file_id, group_id, type_id)
+            }
+            FsError::SectorMismatch { type_id, group_id, sector, field, expected, actual } => {
+                write!(f, "Sector {} {} mismatch for group {} in index {}: expected {}, found {}", sector, field, group_id, type_id, expected, actual)
+            }
+            FsError::BrokenChain { type_id, group_id, sector } => {
+                write!(f, "Broken sector chain at sector {} for group {} in index {}", sector, group_id, type_id)
+            }
+            FsError::InvalidCompression { compression } => write!(f, "Invalid compression type: {}", compression),
+            FsError::LengthMismatch { expected, actual } => write!(f, "Length mismatch: expected {}, found {}", expected, actual),
+            FsError::InvalidKey => write!(f, "Invalid XTEA key"),
+            FsError::InvalidSignature => write!(f, "Invalid checksum table signature"),
+            FsError::Corrupt(message) => write!(f, "{}", message),
+            FsError::InvalidInput(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FsError {
+    fn from(e: io::Error) -> Self {
+        FsError::Io(e)
+    }
+}
+
+impl From<FsError> for io::Error {
+    fn from(e: FsError) -> Self {
+        let kind = match e {
+            FsError::Io(e) => return e,
+            FsError::IndexNotFound { .. } | FsError::GroupNotFound { .. } | FsError::FileNotFound { .. } => ErrorKind::NotFound,
+            FsError::InvalidInput(_) => ErrorKind::InvalidInput,
+            _ => ErrorKind::InvalidData,
+        };
+
+        io::Error::new(kind, e)
+    }
+}
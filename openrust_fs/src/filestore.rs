@@ -1,14 +1,19 @@
-use std::fs::File;
-use std::io::{self, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use crate::container::Container;
+use crate::error::{self, FsError};
 use crate::index::Index;
 use crate::sector::Sector;
+use crate::store::Store;
 
 #[derive(Debug)]
 pub struct FileStore {
     data_channel: File,
     index_channels: Vec<File>,
     meta_channel: File,
+    writable: bool,
 }
 
 impl FileStore {
@@ -16,100 +21,330 @@ impl FileStore {
     const MAIN_FILE_CACHE_META: &'static str = "main_file_cache.idx255";
     const MAIN_FILE_CACHE_INDEX_PREFIX: &'static str = "main_file_cache.idx";
 
-    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Self> {
-        let root = root.as_ref();
-        let data_file = File::open(root.join(Self::MAIN_FILE_CACHE_DATA))?;
-        let meta_file = File::open(root.join(Self::MAIN_FILE_CACHE_META))?;
+    pub fn open<P: AsRef<Path>>(root: P) -> error::Result<Self> {
+        Self::open_impl(root.as_ref(), false)
+    }
+
+    pub fn open_writable<P: AsRef<Path>>(root: P) -> error::Result<Self> {
+        Self::open_impl(root.as_ref(), true)
+    }
+
+    fn open_impl(root: &Path, writable: bool) -> error::Result<Self> {
+        let data_file = Self::open_channel(root.join(Self::MAIN_FILE_CACHE_DATA), writable)?;
+        let meta_file = Self::open_channel(root.join(Self::MAIN_FILE_CACHE_META), writable)?;
         let index_files = (0..=255)
             .map(|i| root.join(format!("{}{}", Self::MAIN_FILE_CACHE_INDEX_PREFIX, i)))
             .take_while(|p| p.exists())
-            .map(File::open)
+            .map(|p| Self::open_channel(p, writable))
             .collect::<io::Result<Vec<_>>>()?;
 
         if index_files.is_empty() {
-            return Err(Error::new(ErrorKind::NotFound, "No index files found"));
+            return Err(FsError::IndexNotFound { type_id: 0 });
         }
 
-        Ok(FileStore { data_channel: data_file, index_channels: index_files, meta_channel: meta_file })
+        Ok(FileStore { data_channel: data_file, index_channels: index_files, meta_channel: meta_file, writable })
     }
 
-    pub fn read(&mut self, type_id: usize, file_id: usize) -> io::Result<Cursor<Vec<u8>>> {
+    pub fn create<P: AsRef<Path>>(root: P, index_count: usize) -> error::Result<Self> {
+        if index_count == 0 || index_count > 255 {
+            return Err(FsError::InvalidInput("Index count must be between 1 and 255".to_string()));
+        }
+
+        let root = root.as_ref();
+        fs::create_dir_all(root)?;
+
+        let data_file = Self::create_channel(root.join(Self::MAIN_FILE_CACHE_DATA))?;
+        let meta_file = Self::create_channel(root.join(Self::MAIN_FILE_CACHE_META))?;
+        let index_files = (0..index_count)
+            .map(|i| root.join(format!("{}{}", Self::MAIN_FILE_CACHE_INDEX_PREFIX, i)))
+            .map(Self::create_channel)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(FileStore { data_channel: data_file, index_channels: index_files, meta_channel: meta_file, writable: true })
+    }
+
+    fn create_channel<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new().read(true).write(true).create_new(true).open(path)
+    }
+
+    fn open_channel<P: AsRef<Path>>(path: P, writable: bool) -> io::Result<File> {
+        OpenOptions::new().read(true).write(writable).open(path)
+    }
+
+    fn check_writable(&self) -> error::Result<()> {
+        if !self.writable {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "FileStore was opened read-only").into());
+        }
+
+        Ok(())
+    }
+
+    fn index_channel(&self, type_id: usize) -> error::Result<&File> {
         if type_id >= self.index_channels.len() && type_id != 255 {
-            let message = format!("Index channel not found for type ID: {}", type_id);
-            return Err(Error::new(ErrorKind::NotFound, message));
+            return Err(FsError::IndexNotFound { type_id });
         }
 
-        let index_channel = if type_id == 255 {
-            &mut self.meta_channel
+        Ok(if type_id == 255 {
+            &self.meta_channel
         } else {
-            &mut self.index_channels[type_id]
-        };
+            &self.index_channels[type_id]
+        })
+    }
+
+    pub fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        let index_channel = self.index_channel(type_id)?;
 
         let ptr = (file_id * Index::SIZE) as u64;
         if ptr >= index_channel.metadata()?.len() {
-            let message = format!("Index entry not found for file ID: {}", file_id);
-            return Err(Error::new(ErrorKind::NotFound, message));
+            return Err(FsError::GroupNotFound { type_id, group_id: file_id });
         }
 
         let mut buf = vec![0; Index::SIZE];
-        index_channel.seek(SeekFrom::Start(ptr))?;
-        index_channel.read_exact(&mut buf)?;
+        read_exact_at(index_channel, &mut buf, ptr)?;
 
         let index = Index::decode(&mut Cursor::new(buf))?;
 
+        let data_size = Sector::data_size(file_id as u32);
+
         let mut data = vec![0; index.size() as usize];
-        let mut buf = vec![0; Sector::SIZE];
 
         let mut ptr = (index.sector() as usize * Sector::SIZE) as u64;
-        let mut remaining = index.size();
+        let mut read = 0;
         let mut chunk = 0;
 
-        while remaining > 0 {
-            self.data_channel.seek(SeekFrom::Start(ptr))?;
-            self.data_channel.read_exact(&mut buf)?;
+        while read < data.len() {
+            let sector = self.read_sector(ptr, type_id, file_id, chunk)?;
+
+            let length = (data.len() - read).min(data_size);
+            data[read..read + length].copy_from_slice(&sector.data()[..length]);
+            read += length;
 
-            let sector = Sector::decode(&mut Cursor::new(&buf))?;
+            chunk += 1;
+            ptr = sector.next_sector() as u64 * Sector::SIZE as u64;
+        }
+
+        Ok(Cursor::new(data))
+    }
+
+    fn read_sector(&self, ptr: u64, type_id: usize, file_id: usize, chunk: u16) -> error::Result<Sector> {
+        let mut buf = vec![0; Sector::SIZE];
+        read_exact_at(&self.data_channel, &mut buf, ptr)?;
 
-            if remaining >= Sector::DATA_SIZE as u32 {
-                data.splice(
-                    (sector.chunk() as usize * Sector::DATA_SIZE)..((sector.chunk() as usize + 1) * Sector::DATA_SIZE),
-                    sector.data().iter().cloned());
-                remaining -= Sector::DATA_SIZE as u32;
+        let sector = Sector::decode(&mut Cursor::new(&buf), Sector::is_extended(file_id as u32))?;
+
+        let mismatch = |field, expected: u32, actual: u32| FsError::SectorMismatch {
+            type_id,
+            group_id: file_id,
+            sector: ptr / Sector::SIZE as u64,
+            field,
+            expected,
+            actual,
+        };
+
+        if sector.type_id() as usize != type_id {
+            return Err(mismatch("type", type_id as u32, sector.type_id() as u32));
+        }
 
-                if sector.type_id() as usize != type_id {
-                    return Err(Error::new(ErrorKind::InvalidData, "File type mismatch"));
+        if sector.id() as usize != file_id {
+            return Err(mismatch("id", file_id as u32, sector.id()));
+        }
+
+        if sector.chunk() != chunk {
+            return Err(mismatch("chunk", chunk as u32, sector.chunk() as u32));
+        }
+
+        Ok(sector)
+    }
+
+    pub fn rebuild_index(&mut self, type_id: usize) -> error::Result<usize> {
+        let max_id = self.get_file_count(type_id)?.saturating_sub(1);
+        self.rebuild_index_with(type_id, max_id)
+    }
+
+    pub fn rebuild_index_with(&mut self, type_id: usize, max_id: usize) -> error::Result<usize> {
+        self.check_writable()?;
+        self.index_channel(type_id)?;
+
+        let max_id = max_id.max(u16::MAX as usize);
+        let extended = if Sector::is_extended(max_id as u32) { &[false, true][..] } else { &[false][..] };
+
+        let sector_count = self.get_sector_count()?;
+        let mut heads = HashMap::new();
+        let mut buf = vec![0; Sector::SIZE];
+
+        self.data_channel.seek(SeekFrom::Start(Sector::SIZE as u64))?;
+        let mut reader = BufReader::new(&self.data_channel);
+        for sector in 1..sector_count {
+            if reader.read_exact(&mut buf).is_err() {
+                break;
+            }
+
+            for extended in extended.iter().copied() {
+                let header = Sector::decode(&mut Cursor::new(&buf), extended)?;
+                if header.type_id() as usize == type_id && header.chunk() == 0 && Sector::is_extended(header.id()) == extended
+                    && header.id() as usize <= max_id {
+                    heads.insert(header.id() as usize, sector);
                 }
+            }
+        }
+
+        let mut ids = heads.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        let mut rebuilt = 0;
+        for file_id in ids {
+            if matches!(self.read(type_id, file_id), Ok(data) if !data.get_ref().is_empty()) {
+                continue;
+            }
+
+            let data = match self.read_chain(type_id, file_id, heads[&file_id], sector_count) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+
+            let mut size = match Container::encoded_length(&data) {
+                Some(size) if size <= data.len() => size,
+                _ => continue,
+            };
+
+            if type_id != 255 && size + 2 <= data.len() {
+                size += 2;
+            }
+
+            let index = Index::new(size as u32, heads[&file_id] as u32);
+            write_all_at(self.index_channel(type_id)?, &index.encode()?, (file_id * Index::SIZE) as u64)?;
+            rebuilt += 1;
+        }
+
+        Ok(rebuilt)
+    }
 
-                if sector.id() as usize != file_id {
-                    return Err(Error::new(ErrorKind::InvalidData, "File id mismatch"));
+    fn read_chain(&self, type_id: usize, file_id: usize, sector: u64, sector_count: u64) -> error::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut ptr = sector * Sector::SIZE as u64;
+        let mut chunk = 0;
+
+        loop {
+            let sector = self.read_sector(ptr, type_id, file_id, chunk)?;
+            data.extend_from_slice(sector.data());
+
+            if sector.next_sector() == 0 {
+                return Ok(data);
+            }
+
+            if sector.next_sector() as u64 >= sector_count || chunk as u64 >= sector_count {
+                return Err(FsError::BrokenChain { type_id, group_id: file_id, sector: ptr / Sector::SIZE as u64 });
+            }
+
+            chunk += 1;
+            ptr = sector.next_sector() as u64 * Sector::SIZE as u64;
+        }
+    }
+
+    pub fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        self.check_writable()?;
+
+        if data.len() > 0xffffff {
+            return Err(FsError::InvalidInput("File too large".to_string()));
+        }
+
+        if type_id > u8::MAX as usize || file_id > u32::MAX as usize {
+            return Err(FsError::InvalidInput("File id out of range".to_string()));
+        }
+
+        if !self.write_impl(type_id, file_id, data, true)? {
+            self.write_impl(type_id, file_id, data, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_impl(&mut self, type_id: usize, file_id: usize, data: &[u8], overwrite: bool) -> error::Result<bool> {
+        let mut overwrite = overwrite;
+        let ptr = (file_id * Index::SIZE) as u64;
+        let index_channel = self.index_channel(type_id)?;
+
+        let mut sector = if overwrite {
+            if ptr + Index::SIZE as u64 > index_channel.metadata()?.len() {
+                return Ok(false);
+            }
+
+            let mut buf = vec![0; Index::SIZE];
+            read_exact_at(index_channel, &mut buf, ptr)?;
+
+            let index = Index::decode(&mut Cursor::new(buf))?;
+            if index.sector() == 0 || index.sector() as u64 > self.get_sector_count()? {
+                return Ok(false);
+            }
+
+            index.sector()
+        } else {
+            self.get_sector_count()?.max(1) as u32
+        };
+
+        let index = Index::new(data.len() as u32, sector);
+        write_all_at(self.index_channel(type_id)?, &index.encode()?, ptr)?;
+
+        let extended = Sector::is_extended(file_id as u32);
+        let data_size = Sector::data_size(file_id as u32);
+
+        let mut buf = vec![0; Sector::SIZE];
+        let mut written = 0;
+        let mut chunk = 0;
+
+        while written < data.len() {
+            let sector_ptr = sector as u64 * Sector::SIZE as u64;
+            let mut next_sector = 0;
+
+            if overwrite {
+                if sector_ptr + Sector::SIZE as u64 > self.data_channel.metadata()?.len() {
+                    return Ok(false);
+                }
+
+                read_exact_at(&self.data_channel, &mut buf, sector_ptr)?;
+
+                let existing = Sector::decode(&mut Cursor::new(&buf), extended)?;
+                if existing.type_id() as usize != type_id || existing.id() as usize != file_id || existing.chunk() != chunk {
+                    return Ok(false);
                 }
 
-                if sector.chunk() != chunk {
-                    return Err(Error::new(ErrorKind::InvalidData, "Chunk mismatch"));
+                next_sector = existing.next_sector();
+                if next_sector as u64 > self.get_sector_count()? {
+                    return Ok(false);
                 }
+            }
 
-                chunk += 1;
-                ptr = sector.next_sector() as u64 * Sector::SIZE as u64;
-            } else {
-                data.splice(
-                    (sector.chunk() as usize * Sector::DATA_SIZE)..,
-                    sector.data()[0..remaining as usize].iter().cloned(),
-                );
+            if next_sector == 0 {
+                overwrite = false;
+                next_sector = self.get_sector_count()?.max(1) as u32;
+                if next_sector == sector {
+                    next_sector += 1;
+                }
+            }
 
-                remaining = 0;
+            let length = (data.len() - written).min(data_size);
+            if length == data.len() - written {
+                next_sector = 0;
             }
+
+            let encoded = Sector::new(type_id as u8, file_id as u32, chunk, next_sector, &data[written..written + length])?.encode()?;
+            write_all_at(&self.data_channel, &encoded, sector_ptr)?;
+
+            written += length;
+            chunk += 1;
+            sector = next_sector;
         }
 
-        return Ok(Cursor::new(data));
+        Ok(true)
     }
 
     pub fn get_type_count(&self) -> usize {
         self.index_channels.len()
     }
 
-    pub fn get_file_count(&self, file_type: usize) -> io::Result<usize> {
+    pub fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
         if file_type >= self.index_channels.len() && file_type != 255 {
-            return Err(Error::new(ErrorKind::NotFound, "Invalid file type"));
+            return Err(FsError::IndexNotFound { type_id: file_type });
         }
 
         if file_type == 255 {
@@ -121,6 +356,11 @@ impl FileStore {
         Ok((index_size / Index::SIZE as u64) as usize)
     }
 
+    fn get_sector_count(&self) -> io::Result<u64> {
+        let data_size = self.data_channel.metadata()?.len();
+        Ok(data_size.div_ceil(Sector::SIZE as u64))
+    }
+
     pub fn data_channel(&self) -> &File {
         &self.data_channel
     }
@@ -144,6 +384,76 @@ impl FileStore {
     pub fn meta_channel_mut(&mut self) -> &mut File {
         &mut self.meta_channel
     }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+impl Store for FileStore {
+    fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        FileStore::read(self, type_id, file_id)
+    }
+
+    fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        FileStore::write(self, type_id, file_id, data)
+    }
+
+    fn get_type_count(&self) -> usize {
+        FileStore::get_type_count(self)
+    }
+
+    fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
+        FileStore::get_file_count(self, file_type)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
 }
 
 impl Drop for FileStore {
@@ -155,4 +465,58 @@ impl Drop for FileStore {
             let _ = channel.flush();
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_data(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let root = std::env::temp_dir().join(format!("openrust_fs_filestore_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let mut store = FileStore::create(&root, 2).unwrap();
+
+        let data = create_data(1200, 1);
+        store.write(0, 7, &data).unwrap();
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), data);
+
+        let sectors = store.get_sector_count().unwrap();
+        let data = create_data(1200, 2);
+        store.write(0, 7, &data).unwrap();
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), data);
+        assert_eq!(store.get_sector_count().unwrap(), sectors);
+
+        store.write(1, 3, &create_data(100, 3)).unwrap();
+        let data = create_data(5000, 4);
+        store.write(0, 7, &data).unwrap();
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), data);
+        assert_eq!(store.read(1, 3).unwrap().into_inner(), create_data(100, 3));
+
+        let sectors = store.get_sector_count().unwrap();
+        let data = create_data(10, 5);
+        store.write(0, 7, &data).unwrap();
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), data);
+        assert_eq!(store.get_sector_count().unwrap(), sectors);
+
+        let data = create_data(3000, 6);
+        store.write(1, 70_000, &data).unwrap();
+        assert_eq!(store.read(1, 70_000).unwrap().into_inner(), data);
+        let data = create_data(3000, 7);
+        store.write(1, 70_000, &data).unwrap();
+        assert_eq!(store.read(1, 70_000).unwrap().into_inner(), data);
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), create_data(10, 5));
+
+        drop(store);
+        let store = FileStore::open(&root).unwrap();
+        assert_eq!(store.read(0, 7).unwrap().into_inner(), create_data(10, 5));
+        assert_eq!(store.read(1, 3).unwrap().into_inner(), create_data(100, 3));
+        assert_eq!(store.read(1, 70_000).unwrap().into_inner(), create_data(3000, 7));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{Cursor, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::error::{self, FsError};
+use crate::store::Store;
+
+#[derive(Debug)]
+pub struct FlatStore {
+    root: PathBuf,
+    type_count: usize,
+}
+
+impl FlatStore {
+    const EXTENSION: &'static str = "dat";
+
+    pub fn open<P: AsRef<Path>>(root: P) -> error::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        if !root.join("255").is_dir() {
+            return Err(FsError::IndexNotFound { type_id: 255 });
+        }
+
+        let type_count = (0..255)
+            .take_while(|i| root.join(i.to_string()).is_dir())
+            .count();
+
+        if type_count == 0 {
+            return Err(FsError::IndexNotFound { type_id: 0 });
+        }
+
+        Ok(FlatStore { root, type_count })
+    }
+
+    pub fn create<P: AsRef<Path>>(root: P, index_count: usize) -> error::Result<Self> {
+        if index_count == 0 || index_count > 255 {
+            return Err(FsError::InvalidInput("Index count must be between 1 and 255".to_string()));
+        }
+
+        let root = root.as_ref().to_path_buf();
+        if root.join("255").exists() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "Store already exists").into());
+        }
+
+        for type_id in (0..index_count).chain([255]) {
+            fs::create_dir_all(root.join(type_id.to_string()))?;
+        }
+
+        Ok(FlatStore { root, type_count: index_count })
+    }
+
+    fn check_type(&self, type_id: usize) -> error::Result<()> {
+        if type_id >= self.type_count && type_id != 255 {
+            return Err(FsError::IndexNotFound { type_id });
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, type_id: usize, file_id: usize) -> PathBuf {
+        self.root.join(type_id.to_string()).join(format!("{}.{}", file_id, Self::EXTENSION))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Store for FlatStore {
+    fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        self.check_type(type_id)?;
+
+        match fs::read(self.path(type_id, file_id)) {
+            Ok(data) => Ok(Cursor::new(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(FsError::GroupNotFound { type_id, group_id: file_id }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        self.check_type(type_id)?;
+
+        let path = self.path(type_id, file_id);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, data)?;
+        Ok(fs::rename(temp, path)?)
+    }
+
+    fn get_type_count(&self) -> usize {
+        self.type_count
+    }
+
+    fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
+        self.check_type(file_type)?;
+
+        let mut count = 0;
+        for entry in fs::read_dir(self.root.join(file_type.to_string()))? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(Self::EXTENSION) {
+                continue;
+            }
+
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<usize>().ok()) {
+                count = count.max(id + 1);
+            }
+        }
+
+        Ok(count)
+    }
+}
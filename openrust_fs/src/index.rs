@@ -1,5 +1,5 @@
 use std::io::{self, Cursor};
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 #[derive(Debug)]
 pub struct Index {
@@ -10,6 +10,10 @@ pub struct Index {
 impl Index {
     pub const SIZE: usize = 6;
 
+    pub fn new(size: u32, sector: u32) -> Self {
+        Self { size, sector }
+    }
+
     pub fn decode(buf: &mut Cursor<Vec<u8>>) -> io::Result<Self> {
         let size = buf.read_u24::<BigEndian>()?;
         let sector = buf.read_u24::<BigEndian>()?;
@@ -17,6 +21,14 @@ impl Index {
         Ok(Self { size, sector })
     }
 
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(Self::SIZE);
+        buf.write_u24::<BigEndian>(self.size)?;
+        buf.write_u24::<BigEndian>(self.sector)?;
+
+        Ok(buf)
+    }
+
     pub fn size(&self) -> u32 {
         self.size
     }
@@ -1,4 +1,4 @@
-use std::io::{self, Cursor, Error, ErrorKind, Read, Write};
+use std::io::{self, Cursor, Error, Read, Write};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bzip2::read::{BzDecoder};
 use bzip2::write::BzEncoder;
@@ -9,16 +9,27 @@ use num_bigint::BigUint;
 use whirlpool::{Whirlpool, Digest};
 use whirlpool::digest::FixedOutput;
 
+pub mod store;
 pub mod filestore;
+pub mod memorystore;
+pub mod flatstore;
+pub mod archive;
 pub mod cache;
 pub mod container;
 pub mod reference_table;
 pub mod checksum_table;
+pub mod xtea;
+pub mod verify;
+pub mod lru;
+pub mod diff;
+pub mod error;
+pub mod rsa;
 mod index;
 mod sector;
 
 const GOLDEN_RATIO: u32 = 0x9E3779B9;
 const ROUNDS: u32 = 32;
+const MAX_PREALLOCATION: usize = 16 * 1024 * 1024;
 
 fn decipher_xtea(buffer: &mut Cursor<Vec<u8>>, offset: usize, length: usize, key: &[i32; 4]) -> io::Result<()> {
     let initial_position = buffer.position();
@@ -47,6 +58,57 @@ fn decipher_xtea(buffer: &mut Cursor<Vec<u8>>, offset: usize, length: usize, key
     Ok(())
 }
 
+fn encipher_xtea(buffer: &mut Cursor<Vec<u8>>, offset: usize, length: usize, key: &[i32; 4]) -> io::Result<()> {
+    let initial_position = buffer.position();
+    let num_quads = (length - offset) / 8;
+
+    for i in 0..num_quads {
+        let mut sum = 0u32;
+        let index = offset + i * 8;
+        buffer.set_position(index as u64);
+
+        let mut v0 = buffer.read_u32::<BigEndian>()?;
+        let mut v1 = buffer.read_u32::<BigEndian>()?;
+
+        for _ in 0..ROUNDS {
+            v0 = v0.wrapping_add((((v1 << 4) ^ (v1 >> 5)).wrapping_add(v1)) ^ (sum.wrapping_add(key[(sum & 3) as usize] as u32)));
+            sum = sum.wrapping_add(GOLDEN_RATIO);
+            v1 = v1.wrapping_add((((v0 << 4) ^ (v0 >> 5)).wrapping_add(v0)) ^ (sum.wrapping_add(key[((sum >> 11) & 3) as usize] as u32)));
+        }
+
+        buffer.set_position(index as u64);
+        buffer.write_u32::<BigEndian>(v0)?;
+        buffer.write_u32::<BigEndian>(v1)?;
+    }
+
+    buffer.set_position(initial_position);
+    Ok(())
+}
+
+pub fn name_hash(name: &str) -> i32 {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if (c as u32) < 256 { c as i32 } else { '?' as i32 })
+        .fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c))
+}
+
+fn read_big_smart(buf: &mut Cursor<Vec<u8>>) -> io::Result<i32> {
+    let peek = buf.get_ref().get(buf.position() as usize).copied().unwrap_or_default();
+    if peek & 0x80 != 0 {
+        Ok(buf.read_i32::<BigEndian>()? & 0x7fffffff)
+    } else {
+        Ok(buf.read_u16::<BigEndian>()? as i32)
+    }
+}
+
+fn write_big_smart(buf: &mut Vec<u8>, value: i32) -> io::Result<()> {
+    if !(0..0x8000).contains(&value) {
+        buf.write_i32::<BigEndian>(value | i32::MIN)
+    } else {
+        buf.write_u16::<BigEndian>(value as u16)
+    }
+}
+
 fn bunzip2(compressed: &[u8]) -> io::Result<Vec<u8>> {
     let mut bzip2 = Vec::with_capacity(compressed.len() + 4);
     bzip2.write_all(b"BZh1")?;
@@ -56,14 +118,17 @@ fn bunzip2(compressed: &[u8]) -> io::Result<Vec<u8>> {
     let mut uncompressed = Vec::new();
     match decoder.read_to_end(&mut uncompressed) {
         Ok(_) => Ok(uncompressed),
-        Err(e) => Err(Error::new(ErrorKind::Other, e))
+        Err(e) => Err(Error::other(e))
     }
 }
 
 fn bzip2(uncompressed: &[u8]) -> io::Result<Vec<u8>> {
-    let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::new(1));
     encoder.write_all(uncompressed)?;
-    encoder.finish()
+
+    let mut compressed = encoder.finish()?;
+    compressed.drain(..4);
+    Ok(compressed)
 }
 
 fn gunzip(compressed: &[u8]) -> io::Result<Vec<u8>> {
@@ -71,7 +136,7 @@ fn gunzip(compressed: &[u8]) -> io::Result<Vec<u8>> {
     let mut uncompressed = Vec::new();
     match decoder.read_to_end(&mut uncompressed) {
         Ok(_) => Ok(uncompressed),
-        Err(e) => Err(Error::new(ErrorKind::Other, e))
+        Err(e) => Err(Error::other(e))
     }
 }
 
@@ -81,9 +146,32 @@ fn gzip(uncompressed: &[u8]) -> io::Result<Vec<u8>> {
     encoder.finish()
 }
 
+fn unlzma(compressed: &[u8], uncompressed_length: usize) -> io::Result<Vec<u8>> {
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(uncompressed_length as u64)),
+        ..Default::default()
+    };
+
+    let mut uncompressed = Vec::with_capacity(uncompressed_length.min(MAX_PREALLOCATION));
+    match lzma_rs::lzma_decompress_with_options(&mut Cursor::new(compressed), &mut uncompressed, &options) {
+        Ok(_) => Ok(uncompressed),
+        Err(e) => Err(Error::other(e))
+    }
+}
+
+fn lzma(uncompressed: &[u8]) -> io::Result<Vec<u8>> {
+    let options = lzma_rs::compress::Options {
+        unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+    };
+
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(&mut Cursor::new(uncompressed), &mut compressed, &options)?;
+    Ok(compressed)
+}
+
 fn get_crc_checksum(buf: &Cursor<Vec<u8>>) -> u32 {
     let mut hasher = Hasher::new();
-    hasher.update(&buf.get_ref());
+    hasher.update(buf.get_ref());
     hasher.finalize()
 }
 
@@ -93,7 +181,7 @@ fn get_whirlpool_digest(buf: &Cursor<Vec<u8>>) -> [u8; 64] {
 
 fn hash_whirlpool(bytes: &Vec<u8>) -> [u8; 64] {
     let mut whirlpool = Whirlpool::new();
-    whirlpool.update(&bytes);
+    whirlpool.update(bytes);
     let result: [u8; 64] = whirlpool.finalize_fixed().into();
     result
 }
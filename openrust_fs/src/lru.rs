@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LruStats {
+    hits: u64,
+    misses: u64,
+    size: usize,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    size: usize,
+    tick: u64,
+    entries: HashMap<K, (V, usize, u64)>,
+    order: BTreeMap<u64, K>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+
+        match self.entries.get_mut(key) {
+            Some((value, _, tick)) => {
+                self.hits += 1;
+                self.order.remove(tick);
+                self.order.insert(self.tick, key.clone());
+                *tick = self.tick;
+                Some(value)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V, weight: usize) {
+        self.remove(&key);
+        if weight > self.capacity {
+            return;
+        }
+
+        self.tick += 1;
+        self.size += weight;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, weight, self.tick));
+
+        while self.size > self.capacity {
+            let (_, oldest) = match self.order.pop_first() {
+                Some(entry) => entry,
+                None => break,
+            };
+
+            if let Some((_, weight, _)) = self.entries.remove(&oldest) {
+                self.size -= weight;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, weight, tick) = self.entries.remove(key)?;
+        self.order.remove(&tick);
+        self.size -= weight;
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.size = 0;
+    }
+
+    pub fn stats(&self) -> LruStats {
+        LruStats { hits: self.hits, misses: self.misses, size: self.size, capacity: self.capacity }
+    }
+}
+
+impl LruStats {
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
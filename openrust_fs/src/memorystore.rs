@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use crate::error::{self, FsError};
+use crate::store::Store;
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    indexes: Vec<HashMap<usize, Vec<u8>>>,
+    meta: HashMap<usize, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new(index_count: usize) -> Self {
+        MemoryStore { indexes: vec![HashMap::new(); index_count], meta: HashMap::new() }
+    }
+
+    fn files(&self, type_id: usize) -> error::Result<&HashMap<usize, Vec<u8>>> {
+        match type_id {
+            255 => Ok(&self.meta),
+            _ => self.indexes.get(type_id).ok_or(FsError::IndexNotFound { type_id }),
+        }
+    }
+
+    fn files_mut(&mut self, type_id: usize) -> error::Result<&mut HashMap<usize, Vec<u8>>> {
+        match type_id {
+            255 => Ok(&mut self.meta),
+            _ => self.indexes.get_mut(type_id).ok_or(FsError::IndexNotFound { type_id }),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        match self.files(type_id)?.get(&file_id) {
+            Some(data) => Ok(Cursor::new(data.clone())),
+            None => Err(FsError::GroupNotFound { type_id, group_id: file_id }),
+        }
+    }
+
+    fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        self.files_mut(type_id)?.insert(file_id, data.to_vec());
+        Ok(())
+    }
+
+    fn get_type_count(&self) -> usize {
+        self.indexes.len()
+    }
+
+    fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
+        Ok(self.files(file_type)?.keys().max().map_or(0, |id| id + 1))
+    }
+}
@@ -1,9 +1,14 @@
 use std::collections::HashMap;
-use std::io::{self, Cursor, Read};
-use byteorder::{BigEndian, ReadBytesExt};
+use std::io::{self, Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use bytes::Buf;
+use crate::error::{self, FsError};
+use crate::{read_big_smart, write_big_smart};
 
-const FLAG_IDENTIFIERS: u8 = 0x01;
-const FLAG_WHIRLPOOL: u8 = 0x02;
+pub const FLAG_IDENTIFIERS: u8 = 0x01;
+pub const FLAG_WHIRLPOOL: u8 = 0x02;
+pub const FLAG_SIZES: u8 = 0x04;
+pub const FLAG_HASH: u8 = 0x08;
 
 #[derive(Debug)]
 pub struct ChildEntry {
@@ -14,7 +19,10 @@ pub struct ChildEntry {
 pub struct Entry {
     identifier: Option<i32>,
     crc: i32,
+    hash: i32,
     whirlpool: [u8; 64],
+    compressed_size: i32,
+    uncompressed_size: i32,
     version: i32,
     entries: HashMap<i32, ChildEntry>,
 }
@@ -28,7 +36,16 @@ pub struct ReferenceTable {
 }
 
 impl ReferenceTable {
-    pub fn decode(buffer: &mut Cursor<Vec<u8>>) -> io::Result<Self> {
+    pub fn new() -> Self {
+        ReferenceTable {
+            format: 6,
+            version: Some(0),
+            flags: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn decode(buffer: &mut Cursor<Vec<u8>>) -> error::Result<Self> {
         let mut table = ReferenceTable {
             format: 0,
             version: None,
@@ -37,34 +54,27 @@ impl ReferenceTable {
         };
 
         table.format = buffer.read_u8()?;
+        if !(5..=7).contains(&table.format) {
+            return Err(FsError::Corrupt(format!("Unsupported reference table format: {}", table.format)));
+        }
+
         if table.format >= 6 {
             table.version = Some(buffer.read_i32::<BigEndian>()?);
         }
         table.flags = buffer.read_u8()?;
 
-        let ids_length = buffer.read_u16::<BigEndian>()? as usize;
-        let mut ids = vec![0; ids_length];
+        let ids_length = table.read_smart(buffer)? as usize;
+        let mut ids = Vec::with_capacity(ids_length.min(buffer.remaining()));
         let mut accumulator = 0;
-        let mut size = -1;
 
-        for i in 0..ids.len() {
-            let delta = buffer.read_i16::<BigEndian>()?;
-            ids[i] = accumulator + i32::from(delta);
-            accumulator = ids[i];
-            if ids[i] > size {
-                size = ids[i];
-            }
+        for _ in 0..ids_length {
+            let delta = table.read_smart(buffer)?;
+            accumulator = Self::accumulate(accumulator, delta)?;
+            ids.push(accumulator);
         }
-        size += 1;
 
         for id in &ids {
-            table.entries.insert(*id, Entry {
-                identifier: None,
-                crc: 0,
-                whirlpool: [0; 64],
-                version: 0,
-                entries: HashMap::new(),
-            });
+            table.entries.insert(*id, Entry::new());
         }
 
         if table.flags & FLAG_IDENTIFIERS != 0 {
@@ -79,45 +89,60 @@ impl ReferenceTable {
             entry.crc = buffer.read_i32::<BigEndian>()?;
         }
 
+        if table.flags & FLAG_HASH != 0 {
+            for id in &ids {
+                let entry = table.entries.get_mut(id).unwrap();
+                entry.hash = buffer.read_i32::<BigEndian>()?;
+            }
+        }
+
         if table.flags & FLAG_WHIRLPOOL != 0 {
             for id in &ids {
                 buffer.read_exact(&mut table.entries.get_mut(id).unwrap().whirlpool)?;
             }
         }
 
+        if table.flags & FLAG_SIZES != 0 {
+            for id in &ids {
+                let entry = table.entries.get_mut(id).unwrap();
+                entry.compressed_size = buffer.read_i32::<BigEndian>()?;
+                entry.uncompressed_size = buffer.read_i32::<BigEndian>()?;
+            }
+        }
+
         for id in &ids {
             let entry = table.entries.get_mut(id).unwrap();
             entry.version = buffer.read_i32::<BigEndian>()?;
         }
 
-        let mut members = vec![vec![]; size as usize];
-        for id in &ids {
-            let child_size = buffer.read_u16::<BigEndian>()?;
-            members[*id as usize] = vec![0; child_size as usize];
+        let mut members = Vec::with_capacity(ids.len());
+        for _ in &ids {
+            members.push(table.read_smart(buffer)? as usize);
         }
 
-        for id in &ids {
-            accumulator = 0;
-            size = -1;
-
-            for i in 0..members[*id as usize].len() {
-                let delta = buffer.read_i16::<BigEndian>()?;
-                members[*id as usize][i] = accumulator + i32::from(delta);
-                accumulator = members[*id as usize][i];
-                if members[*id as usize][i] > size {
-                    size = members[*id as usize][i];
-                }
+        let members = members.into_iter().map(|child_size| {
+            let mut children = Vec::with_capacity(child_size.min(buffer.remaining()));
+            let mut accumulator = 0;
+
+            for _ in 0..child_size {
+                let delta = table.read_smart(buffer)?;
+                accumulator = Self::accumulate(accumulator, delta)?;
+                children.push(accumulator);
             }
 
-            for child in &members[*id as usize] {
-                let entry = table.entries.get_mut(id).unwrap();
-                entry.entries.insert(*child, ChildEntry { identifier: None });
+            Ok(children)
+        }).collect::<error::Result<Vec<_>>>()?;
+
+        for (id, children) in ids.iter().zip(&members) {
+            let entry = table.entries.get_mut(id).unwrap();
+            for child in children {
+                entry.entries.insert(*child, ChildEntry::new());
             }
         }
 
         if table.flags & FLAG_IDENTIFIERS != 0 {
-            for id in &ids {
-                for child in &members[*id as usize] {
+            for (id, children) in ids.iter().zip(&members) {
+                for child in children.iter() {
                     let entry = table.entries.get_mut(id).unwrap().entries.get_mut(child).unwrap();
                     entry.identifier = Some(buffer.read_i32::<BigEndian>()?);
                 }
@@ -127,6 +152,110 @@ impl ReferenceTable {
         Ok(table)
     }
 
+    pub fn encode(&self) -> error::Result<Cursor<Vec<u8>>> {
+        let mut buf = Vec::new();
+        buf.write_u8(self.format)?;
+        if self.format >= 6 {
+            buf.write_i32::<BigEndian>(self.version.unwrap_or_default())?;
+        }
+        buf.write_u8(self.flags)?;
+
+        let mut ids = self.entries.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        self.write_smart(&mut buf, ids.len() as i32)?;
+        let mut accumulator = 0;
+        for id in &ids {
+            self.write_smart(&mut buf, *id - accumulator)?;
+            accumulator = *id;
+        }
+
+        let entries = ids.iter().map(|id| &self.entries[id]).collect::<Vec<_>>();
+
+        if self.flags & FLAG_IDENTIFIERS != 0 {
+            for entry in &entries {
+                buf.write_i32::<BigEndian>(entry.identifier.unwrap_or_default())?;
+            }
+        }
+
+        for entry in &entries {
+            buf.write_i32::<BigEndian>(entry.crc)?;
+        }
+
+        if self.flags & FLAG_HASH != 0 {
+            for entry in &entries {
+                buf.write_i32::<BigEndian>(entry.hash)?;
+            }
+        }
+
+        if self.flags & FLAG_WHIRLPOOL != 0 {
+            for entry in &entries {
+                buf.write_all(&entry.whirlpool)?;
+            }
+        }
+
+        if self.flags & FLAG_SIZES != 0 {
+            for entry in &entries {
+                buf.write_i32::<BigEndian>(entry.compressed_size)?;
+                buf.write_i32::<BigEndian>(entry.uncompressed_size)?;
+            }
+        }
+
+        for entry in &entries {
+            buf.write_i32::<BigEndian>(entry.version)?;
+        }
+
+        let members = entries.iter()
+            .map(|entry| {
+                let mut children = entry.entries.keys().copied().collect::<Vec<_>>();
+                children.sort_unstable();
+                children
+            })
+            .collect::<Vec<_>>();
+
+        for children in &members {
+            self.write_smart(&mut buf, children.len() as i32)?;
+        }
+
+        for children in &members {
+            accumulator = 0;
+            for child in children {
+                self.write_smart(&mut buf, *child - accumulator)?;
+                accumulator = *child;
+            }
+        }
+
+        if self.flags & FLAG_IDENTIFIERS != 0 {
+            for (entry, children) in entries.iter().zip(&members) {
+                for child in children {
+                    buf.write_i32::<BigEndian>(entry.entries[child].identifier.unwrap_or_default())?;
+                }
+            }
+        }
+
+        Ok(Cursor::new(buf))
+    }
+
+    fn accumulate(accumulator: i32, delta: i32) -> error::Result<i32> {
+        accumulator.checked_add(delta).ok_or_else(|| FsError::Corrupt("Reference table id overflow".to_string()))
+    }
+
+    fn read_smart(&self, buffer: &mut Cursor<Vec<u8>>) -> io::Result<i32> {
+        if self.format >= 7 {
+            read_big_smart(buffer)
+        } else {
+            Ok(buffer.read_u16::<BigEndian>()? as i32)
+        }
+    }
+
+    fn write_smart(&self, buf: &mut Vec<u8>, value: i32) -> io::Result<()> {
+        if self.format >= 7 {
+            write_big_smart(buf, value)
+        } else {
+            buf.write_u16::<BigEndian>(value as u16)
+        }
+    }
+
     pub fn format(&self) -> u8 {
         self.format
     }
@@ -151,6 +280,12 @@ impl ReferenceTable {
         self.flags = flags;
     }
 
+    pub fn find_entry(&self, identifier: i32) -> Option<i32> {
+        self.entries.iter()
+            .find(|(_, entry)| entry.identifier == Some(identifier))
+            .map(|(id, _)| *id)
+    }
+
     pub fn entries(&self) -> &HashMap<i32, Entry> {
         &self.entries
     }
@@ -160,7 +295,26 @@ impl ReferenceTable {
     }
 }
 
+impl Default for ReferenceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Entry {
+    pub fn new() -> Self {
+        Entry {
+            identifier: None,
+            crc: 0,
+            hash: 0,
+            whirlpool: [0; 64],
+            compressed_size: 0,
+            uncompressed_size: 0,
+            version: 0,
+            entries: HashMap::new(),
+        }
+    }
+
     pub fn identifier(&self) -> Option<i32> {
         self.identifier
     }
@@ -177,6 +331,14 @@ impl Entry {
         self.crc = crc;
     }
 
+    pub fn hash(&self) -> i32 {
+        self.hash
+    }
+
+    pub fn set_hash(&mut self, hash: i32) {
+        self.hash = hash;
+    }
+
     pub fn whirlpool(&self) -> &[u8; 64] {
         &self.whirlpool
     }
@@ -185,6 +347,22 @@ impl Entry {
         self.whirlpool = whirlpool;
     }
 
+    pub fn compressed_size(&self) -> i32 {
+        self.compressed_size
+    }
+
+    pub fn set_compressed_size(&mut self, compressed_size: i32) {
+        self.compressed_size = compressed_size;
+    }
+
+    pub fn uncompressed_size(&self) -> i32 {
+        self.uncompressed_size
+    }
+
+    pub fn set_uncompressed_size(&mut self, uncompressed_size: i32) {
+        self.uncompressed_size = uncompressed_size;
+    }
+
     pub fn version(&self) -> i32 {
         self.version
     }
@@ -193,6 +371,12 @@ impl Entry {
         self.version = version;
     }
 
+    pub fn find_entry(&self, identifier: i32) -> Option<i32> {
+        self.entries.iter()
+            .find(|(_, entry)| entry.identifier == Some(identifier))
+            .map(|(id, _)| *id)
+    }
+
     pub fn entries(&self) -> &HashMap<i32, ChildEntry> {
         &self.entries
     }
@@ -202,7 +386,17 @@ impl Entry {
     }
 }
 
+impl Default for Entry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ChildEntry {
+    pub fn new() -> Self {
+        ChildEntry { identifier: None }
+    }
+
     pub fn identifier(&self) -> Option<i32> {
         self.identifier
     }
@@ -210,4 +404,148 @@ impl ChildEntry {
     pub fn set_identifier(&mut self, identifier: Option<i32>) {
         self.identifier = identifier;
     }
-}
\ No newline at end of file
+}
+
+impl Default for ChildEntry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+    use crate::filestore::FileStore;
+
+    const FLAGS: [u8; 6] = [0, FLAG_IDENTIFIERS, FLAG_WHIRLPOOL, FLAG_SIZES, FLAG_HASH, FLAG_IDENTIFIERS | FLAG_WHIRLPOOL | FLAG_SIZES | FLAG_HASH];
+
+    fn create_table(format: u8, flags: u8) -> ReferenceTable {
+        let mut table = ReferenceTable::new();
+        table.set_format(format);
+        table.set_version(if format >= 6 { Some(0x01020304) } else { None });
+        table.set_flags(flags);
+
+        let mut ids = vec![0, 1, 5, 300, 32767];
+        if format >= 7 {
+            ids.extend([32768, 100_000]);
+        }
+
+        for (i, id) in ids.into_iter().enumerate() {
+            let i = i as i32;
+            let mut entry = Entry::new();
+            if flags & FLAG_IDENTIFIERS != 0 {
+                entry.set_identifier(Some(-1000 * i - 7));
+            }
+            entry.set_crc(0x1234_5678 ^ i);
+            if flags & FLAG_HASH != 0 {
+                entry.set_hash(0x0bad_f00d + i);
+            }
+            if flags & FLAG_WHIRLPOOL != 0 {
+                entry.set_whirlpool([i as u8 + 1; 64]);
+            }
+            if flags & FLAG_SIZES != 0 {
+                entry.set_compressed_size(100 + i);
+                entry.set_uncompressed_size(200 + i);
+            }
+            entry.set_version(i * 3);
+
+            for child in (0..i * 2).step_by(2) {
+                let mut child_entry = ChildEntry::new();
+                if flags & FLAG_IDENTIFIERS != 0 {
+                    child_entry.set_identifier(Some(child * 31));
+                }
+                entry.entries_mut().insert(child, child_entry);
+            }
+
+            table.entries_mut().insert(id, entry);
+        }
+
+        table
+    }
+
+    #[test]
+    fn round_trip_all_formats_and_flags() {
+        for format in [5, 6, 7] {
+            for flags in FLAGS {
+                let table = create_table(format, flags);
+                let encoded = table.encode().unwrap().into_inner();
+
+                let decoded = ReferenceTable::decode(&mut Cursor::new(encoded.clone())).unwrap();
+                assert_eq!(decoded.format(), format);
+                assert_eq!(decoded.flags(), flags);
+                assert_eq!(decoded.version(), table.version());
+                assert_eq!(decoded.entries().len(), table.entries().len());
+
+                for (id, entry) in table.entries() {
+                    let other = &decoded.entries()[id];
+                    assert_eq!(other.identifier(), entry.identifier());
+                    assert_eq!(other.crc(), entry.crc());
+                    assert_eq!(other.hash(), entry.hash());
+                    assert_eq!(other.whirlpool(), entry.whirlpool());
+                    assert_eq!(other.compressed_size(), entry.compressed_size());
+                    assert_eq!(other.uncompressed_size(), entry.uncompressed_size());
+                    assert_eq!(other.version(), entry.version());
+                    assert_eq!(other.entries().len(), entry.entries().len());
+
+                    for (child, child_entry) in entry.entries() {
+                        assert_eq!(other.entries()[child].identifier(), child_entry.identifier());
+                    }
+                }
+
+                assert_eq!(decoded.encode().unwrap().into_inner(), encoded, "format {} flags {}", format, flags);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_encoded_bytes() {
+        let bytes = vec![
+            5, FLAG_IDENTIFIERS,
+            0, 2, 0, 1, 0, 3,
+            0, 0, 0, 42, 0, 0, 0, 43,
+            0, 0, 0, 1, 0, 0, 0, 2,
+            0, 0, 0, 7, 0, 0, 0, 8,
+            0, 1, 0, 2,
+            0, 0,
+            0, 0, 0, 1,
+            0, 0, 0, 9, 0, 0, 0, 10, 0, 0, 0, 11,
+        ];
+
+        let table = ReferenceTable::decode(&mut Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(table.find_entry(43), Some(4));
+        assert_eq!(table.entries()[&4].find_entry(11), Some(1));
+        assert_eq!(table.encode().unwrap().into_inner(), bytes);
+    }
+
+    #[test]
+    fn round_trip_real_cache() {
+        let root = std::env::temp_dir().join(format!("openrust_fs_reference_table_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let tables = [(5, 0), (6, FLAG_IDENTIFIERS | FLAG_WHIRLPOOL), (7, FLAG_SIZES | FLAG_HASH), (7, FLAGS[5])];
+        let mut cache = Cache::create(&root, tables.len()).unwrap();
+        for (type_id, (format, flags)) in tables.into_iter().enumerate() {
+            cache.write_reference_table(type_id, &create_table(format, flags)).unwrap();
+        }
+        drop(cache);
+
+        let cache = Cache::new(FileStore::open(&root).unwrap());
+        assert_eq!(cache.store().get_file_count(255).unwrap(), tables.len());
+
+        for type_id in 0..cache.store().get_file_count(255).unwrap() {
+            let container = cache.read_container(255, type_id).unwrap();
+            let bytes = container.data().get_ref().clone();
+            let table = ReferenceTable::decode(&mut Cursor::new(bytes.clone())).unwrap();
+            assert_eq!(table.encode().unwrap().into_inner(), bytes, "index {}", type_id);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn id_overflow_is_corrupt() {
+        let bytes = vec![7, 0, 0, 0, 0, 0, 0, 2, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(ReferenceTable::decode(&mut Cursor::new(bytes)), Err(FsError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use num_bigint::BigUint;
+use rand::rngs::OsRng;
+use ::rsa::traits::{PrivateKeyParts, PublicKeyParts};
+
+const PUBLIC_EXPONENT: u32 = 65537;
+
+#[derive(Debug, Clone)]
+pub struct RsaKey {
+    modulus: BigUint,
+    public_exponent: BigUint,
+    private_exponent: Option<BigUint>,
+}
+
+impl RsaKey {
+    pub fn new(modulus: BigUint, public_exponent: BigUint, private_exponent: Option<BigUint>) -> Self {
+        RsaKey { modulus, public_exponent, private_exponent }
+    }
+
+    pub fn generate(bits: u64) -> io::Result<Self> {
+        if bits < 576 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Key must be at least 576 bits to sign a whirlpool digest"));
+        }
+
+        let key = ::rsa::RsaPrivateKey::new(&mut OsRng, bits as usize).map_err(Error::other)?;
+        Ok(RsaKey::new(convert(key.n()), convert(key.e()), Some(convert(key.d()))))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut modulus = None;
+        let mut public_exponent = None;
+        let mut private_exponent = None;
+
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once('=')
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Invalid key line: {}", line)))?;
+            let value = value.trim().parse::<BigUint>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+            match name.trim() {
+                "modulus" => modulus = Some(value),
+                "public_exponent" => public_exponent = Some(value),
+                "private_exponent" => private_exponent = Some(value),
+                name => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown key field: {}", name))),
+            }
+        }
+
+        let modulus = modulus.ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing modulus"))?;
+        let public_exponent = public_exponent.unwrap_or_else(|| BigUint::from(PUBLIC_EXPONENT));
+
+        Ok(RsaKey::new(modulus, public_exponent, private_exponent))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut contents = format!("modulus={}\npublic_exponent={}\n", self.modulus, self.public_exponent);
+        if let Some(private_exponent) = &self.private_exponent {
+            contents.push_str(&format!("private_exponent={}\n", private_exponent));
+        }
+
+        fs::write(path, contents)
+    }
+
+    pub fn modulus(&self) -> &BigUint {
+        &self.modulus
+    }
+
+    pub fn public_exponent(&self) -> &BigUint {
+        &self.public_exponent
+    }
+
+    pub fn private_exponent(&self) -> Option<&BigUint> {
+        self.private_exponent.as_ref()
+    }
+}
+
+fn convert(value: &::rsa::BigUint) -> BigUint {
+    BigUint::from_bytes_be(&value.to_bytes_be())
+}
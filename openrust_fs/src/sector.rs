@@ -1,41 +1,78 @@
 use bytes::Buf;
 use std::io::{self, Cursor, Error, ErrorKind, prelude::*};
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 #[derive(Debug)]
 pub struct Sector {
     type_id: u8,
-    id: u16,
+    id: u32,
     chunk: u16,
     next_sector: u32,
-    data: [u8; Sector::DATA_SIZE],
+    data: Vec<u8>,
 }
 
 impl Sector {
     pub const HEADER_SIZE: usize = 8;
     pub const DATA_SIZE: usize = 512;
     pub const SIZE: usize = Self::HEADER_SIZE + Self::DATA_SIZE;
+    pub const EXTENDED_HEADER_SIZE: usize = 10;
+    pub const EXTENDED_DATA_SIZE: usize = Self::SIZE - Self::EXTENDED_HEADER_SIZE;
 
-    pub fn decode(buf: &mut Cursor<&Vec<u8>>) -> io::Result<Self> {
+    pub fn new(type_id: u8, id: u32, chunk: u16, next_sector: u32, data: &[u8]) -> io::Result<Self> {
+        let data_size = Self::data_size(id);
+        if data.len() > data_size {
+            return Err(Error::new(ErrorKind::InvalidInput, "Sector data too large"));
+        }
+
+        let mut padded = vec![0; data_size];
+        padded[..data.len()].copy_from_slice(data);
+
+        Ok(Self { type_id, id, chunk, next_sector, data: padded })
+    }
+
+    pub fn is_extended(id: u32) -> bool {
+        id > u16::MAX as u32
+    }
+
+    pub fn data_size(id: u32) -> usize {
+        if Self::is_extended(id) { Self::EXTENDED_DATA_SIZE } else { Self::DATA_SIZE }
+    }
+
+    pub fn decode(buf: &mut Cursor<&Vec<u8>>, extended: bool) -> io::Result<Self> {
         if buf.remaining() != Self::SIZE {
             return Err(Error::new(ErrorKind::InvalidData, "Invalid buffer size"));
         }
 
-        let id = buf.read_u16::<BigEndian>()?;
+        let id = if extended { buf.read_u32::<BigEndian>()? } else { buf.read_u16::<BigEndian>()? as u32 };
         let chunk = buf.read_u16::<BigEndian>()?;
         let next_sector = buf.read_u24::<BigEndian>()? & 0x00ffffff;
         let type_id = buf.read_u8()?;
-        let mut data = [0u8; Self::DATA_SIZE];
+        let mut data = vec![0u8; if extended { Self::EXTENDED_DATA_SIZE } else { Self::DATA_SIZE }];
         buf.read_exact(&mut data)?;
 
         Ok(Self { type_id, id, chunk, next_sector, data })
     }
 
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(Self::SIZE);
+        if Self::is_extended(self.id) {
+            buf.write_u32::<BigEndian>(self.id)?;
+        } else {
+            buf.write_u16::<BigEndian>(self.id as u16)?;
+        }
+        buf.write_u16::<BigEndian>(self.chunk)?;
+        buf.write_u24::<BigEndian>(self.next_sector)?;
+        buf.write_u8(self.type_id)?;
+        buf.write_all(&self.data)?;
+
+        Ok(buf)
+    }
+
     pub fn type_id(&self) -> u8 {
         self.type_id
     }
     
-    pub fn id(&self) -> u16 {
+    pub fn id(&self) -> u32 {
         self.id
     }
 
@@ -47,7 +84,7 @@ impl Sector {
         self.next_sector
     }
 
-    pub fn data(&self) -> &[u8; Sector::DATA_SIZE] {
+    pub fn data(&self) -> &[u8] {
         &self.data
     }
-}
\ No newline at end of file
+}
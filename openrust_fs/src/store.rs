@@ -0,0 +1,30 @@
+use std::io::Cursor;
+use crate::error;
+
+pub trait Store {
+    fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>>;
+
+    fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()>;
+
+    fn get_type_count(&self) -> usize;
+
+    fn get_file_count(&self, file_type: usize) -> error::Result<usize>;
+}
+
+impl<T: Store + ?Sized> Store for Box<T> {
+    fn read(&self, type_id: usize, file_id: usize) -> error::Result<Cursor<Vec<u8>>> {
+        (**self).read(type_id, file_id)
+    }
+
+    fn write(&mut self, type_id: usize, file_id: usize, data: &[u8]) -> error::Result<()> {
+        (**self).write(type_id, file_id, data)
+    }
+
+    fn get_type_count(&self) -> usize {
+        (**self).get_type_count()
+    }
+
+    fn get_file_count(&self, file_type: usize) -> error::Result<usize> {
+        (**self).get_file_count(file_type)
+    }
+}
@@ -0,0 +1,78 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    Missing,
+    Truncated,
+    BrokenChain(String),
+    CrcMismatch { expected: i32, actual: i32 },
+    WhirlpoolMismatch,
+    Undecodable(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    type_id: usize,
+    group_id: usize,
+    fault: Fault,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    groups_checked: usize,
+    issues: Vec<Issue>,
+}
+
+impl Issue {
+    pub fn new(type_id: usize, group_id: usize, fault: Fault) -> Self {
+        Issue { type_id, group_id, fault }
+    }
+
+    pub fn type_id(&self) -> usize {
+        self.type_id
+    }
+
+    pub fn group_id(&self) -> usize {
+        self.group_id
+    }
+
+    pub fn fault(&self) -> &Fault {
+        &self.fault
+    }
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| i.fault == Fault::Missing)
+    }
+
+    pub fn truncated(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| matches!(i.fault, Fault::Truncated | Fault::BrokenChain(_)))
+    }
+
+    pub fn mismatched(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| matches!(i.fault, Fault::CrcMismatch { .. } | Fault::WhirlpoolMismatch))
+    }
+
+    pub fn undecodable(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|i| matches!(i.fault, Fault::Undecodable(_)))
+    }
+
+    pub fn groups_checked(&self) -> usize {
+        self.groups_checked
+    }
+
+    pub fn set_groups_checked(&mut self, groups_checked: usize) {
+        self.groups_checked = groups_checked;
+    }
+
+    pub fn issues(&self) -> &Vec<Issue> {
+        &self.issues
+    }
+
+    pub fn issues_mut(&mut self) -> &mut Vec<Issue> {
+        &mut self.issues
+    }
+}
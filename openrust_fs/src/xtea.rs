@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default)]
+pub struct XteaKeyStore {
+    keys: HashMap<u32, [i32; 4]>,
+}
+
+#[derive(Debug, Default)]
+pub struct KeyReport {
+    valid: Vec<u32>,
+    invalid: Vec<u32>,
+    missing: Vec<u32>,
+}
+
+impl XteaKeyStore {
+    pub fn new() -> Self {
+        XteaKeyStore { keys: HashMap::new() }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            Self::load_directory(path)
+        } else {
+            Self::load_json(path)
+        }
+    }
+
+    pub fn load_directory<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let mut store = XteaKeyStore::new();
+
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("txt") {
+                continue;
+            }
+
+            let region = path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u32>().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Invalid key file name: {}", path.display())))?;
+
+            let values = fs::read_to_string(&path)?
+                .split_whitespace()
+                .map(|s| s.parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+            store.insert(region, Self::to_key(&values)?);
+        }
+
+        Ok(store)
+    }
+
+    pub fn load_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut store = XteaKeyStore::new();
+
+        match json {
+            Value::Object(map) => {
+                for (region, key) in map {
+                    let region = region.parse::<u32>()
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                    store.insert(region, Self::parse_key(&key)?);
+                }
+            }
+            Value::Array(entries) => {
+                for entry in entries {
+                    let region = ["region", "mapsquare", "id"].iter()
+                        .find_map(|name| entry.get(name))
+                        .and_then(Value::as_u64)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing region id"))?;
+                    let key = ["keys", "key"].iter()
+                        .find_map(|name| entry.get(name))
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing region key"))?;
+
+                    store.insert(region as u32, Self::parse_key(key)?);
+                }
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported key file layout")),
+        }
+
+        Ok(store)
+    }
+
+    fn parse_key(value: &Value) -> io::Result<[i32; 4]> {
+        let values = value.as_array()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Region key is not an array"))?
+            .iter()
+            .map(|v| v.as_i64().map(|v| v as i32))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Region key is not numeric"))?;
+
+        Self::to_key(&values)
+    }
+
+    fn to_key(values: &[i32]) -> io::Result<[i32; 4]> {
+        values.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, "Region key must have 4 values"))
+    }
+
+    pub fn get_key(&self, region: u32) -> Option<&[i32; 4]> {
+        self.keys.get(&region)
+    }
+
+    pub fn insert(&mut self, region: u32, key: [i32; 4]) {
+        self.keys.insert(region, key);
+    }
+
+    pub fn keys(&self) -> &HashMap<u32, [i32; 4]> {
+        &self.keys
+    }
+
+    pub fn keys_mut(&mut self) -> &mut HashMap<u32, [i32; 4]> {
+        &mut self.keys
+    }
+}
+
+impl KeyReport {
+    pub fn valid(&self) -> &Vec<u32> {
+        &self.valid
+    }
+
+    pub fn valid_mut(&mut self) -> &mut Vec<u32> {
+        &mut self.valid
+    }
+
+    pub fn invalid(&self) -> &Vec<u32> {
+        &self.invalid
+    }
+
+    pub fn invalid_mut(&mut self) -> &mut Vec<u32> {
+        &mut self.invalid
+    }
+
+    pub fn missing(&self) -> &Vec<u32> {
+        &self.missing
+    }
+
+    pub fn missing_mut(&mut self) -> &mut Vec<u32> {
+        &mut self.missing
+    }
+}
@@ -1,57 +1,102 @@
 extern crate core;
 
-use std::io::{self, Cursor, Error, ErrorKind, Read};
+use std::io::{self, Error, ErrorKind};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::Arc;
 use bytes::{Buf, BufMut, BytesMut};
 use futures::{SinkExt, StreamExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Instant};
 use tokio_util::codec::{Decoder, Encoder, Framed};
-use openrust_fs::cache::Cache;
-use openrust_fs::checksum_table::ChecksumTable;
-use openrust_fs::container::{self, Container};
-use openrust_fs::filestore::FileStore;
+use openrust_fs::rsa::RsaKey;
+use openrust_fs::store::Store;
+use crate::scheduler::{FileRequest, Scheduler};
+use crate::server::GameServer;
+use crate::update::UpdateService;
+
+mod scheduler;
+mod server;
+mod update;
 
 const HANDSHAKE_UPDATE: u8 = 15;
 const VERSION: u32 = 530;
+const WHIRLPOOL_REVISION: u32 = 578;
+const REVISIONS: [u32; 1] = [VERSION];
+
+const CACHE_PATH: &str = "openrust_data/fs/";
+const RSA_KEY_PATH: &str = "openrust_data/rsa.key";
+const RSA_KEY_BITS: u64 = 1024;
 
 const STATUS_OK: u8 = 0;
 const STATUS_OUT_OF_DATE: u8 = 6;
 
-#[derive(Debug)]
-pub struct GameServer {
-    cache: Arc<Mutex<Cache>>,
-    checksum_table: ChecksumTable,
-}
+const REQUEST_SIZE: usize = 4;
 
-impl GameServer {
-    pub fn new() -> io::Result<Self> {
-        let cache = Arc::new(Mutex::new(Cache::new(FileStore::open("openrust_data/fs/")?)));
-        let checksum_table = cache.lock().unwrap().create_checksum_table()?;
-
-        Ok(Self { cache, checksum_table })
-    }
-}
+const OPCODE_PREFETCH: u8 = 0;
+const OPCODE_URGENT: u8 = 1;
+const OPCODE_LOGGED_IN: u8 = 2;
+const OPCODE_LOGGED_OUT: u8 = 3;
+const OPCODE_ENCRYPTION: u8 = 4;
+const OPCODE_CONNECTION_INITIALISED: u8 = 6;
+const OPCODE_CONNECTION_CLOSED: u8 = 7;
 
 #[derive(Debug)]
 pub enum GameMessage {
     UpdateStatus { status_id: u8 },
-    FileResponse { type_id: u8, file_id: u16, priority: bool, container: Cursor<Vec<u8>> },
+    FileRequest(FileRequest),
+    FileResponse { priority: bool, data: Arc<Vec<u8>> },
+    Initialised,
+    Close,
 }
 
 enum GameState {
     Handshake,
     Update,
+    Closed,
 }
 
 pub struct GameDecoder {
     state: GameState,
-    server: Arc<GameServer>,
+    revisions: Arc<[u32]>,
+    revision: u32,
+    initialised: bool,
+    logged_in: bool,
+    encryption_key: u8,
 }
 
 impl GameDecoder {
-    pub fn new(server: Arc<GameServer>) -> Self {
-        Self { state: GameState::Handshake, server }
+    pub fn new() -> Self {
+        Self::with_revisions(Arc::new(REVISIONS))
+    }
+
+    pub fn with_revisions(revisions: Arc<[u32]>) -> Self {
+        Self {
+            state: GameState::Handshake,
+            revisions,
+            revision: VERSION,
+            initialised: false,
+            logged_in: false,
+            encryption_key: 0,
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn is_initialised(&self) -> bool {
+        self.initialised
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+    }
+}
+
+impl Default for GameDecoder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -62,12 +107,21 @@ impl Decoder for GameDecoder {
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match self.state {
             GameState::Handshake => {
-                let service_id = src.get_u8();
-                return match service_id {
+                if src.is_empty() {
+                    return Ok(None);
+                }
+
+                return match src[0] {
                     HANDSHAKE_UPDATE => {
+                        if src.len() < 5 {
+                            return Ok(None);
+                        }
+
+                        src.advance(1);
                         let version = src.get_u32();
-                        let status_id = if version != VERSION { STATUS_OUT_OF_DATE } else { STATUS_OK };
+                        let status_id = if self.revisions.contains(&version) { STATUS_OK } else { STATUS_OUT_OF_DATE };
                         if status_id == STATUS_OK {
+                            self.revision = version;
                             self.state = GameState::Update;
                         }
 
@@ -77,39 +131,34 @@ impl Decoder for GameDecoder {
                 };
             }
             GameState::Update => {
-                while src.has_remaining()  {
+                while src.len() >= REQUEST_SIZE {
                     let opcode = src.get_u8();
                     match opcode {
-                        0 | 1 => {
-                            if src.remaining() < 3 {
-                                /*
-                                  todo: strange edge-case when I get to "loading interfaces" there's
-                                   not enough bytes in the buffer... this seems to be a "hacky" way
-                                   of bypassing it but I need to figure out the cause behind this.
-                                 */
-                                return Ok(None);
-                            }
-
+                        OPCODE_PREFETCH | OPCODE_URGENT => {
                             let type_id = src.get_u8();
                             let file_id = src.get_u16();
-                            let priority = opcode == 1;
-                            let container = if type_id == 255 && file_id == 255 {
-                                let table = &self.server.checksum_table;
-                                let container = Container::new(container::COMPRESSION_NONE, table.encode()?);
-                                Cursor::new(container.encode()?.into_inner())
-                            } else {
-                                let mut cache = self.server.cache.lock().expect("Failed to acquire lock");
-                                let mut data = cache.store_mut().read(type_id as usize, file_id as usize)?.into_inner();
-
-                                if type_id != 255 {
-                                    let len = data.len();
-                                    data.truncate(len - 2);
-                                }
-
-                                Cursor::new(data)
-                            };
-
-                            return Ok(Some(GameMessage::FileResponse { type_id, file_id, priority, container }));
+                            let priority = opcode == OPCODE_URGENT;
+
+                            return Ok(Some(GameMessage::FileRequest(FileRequest::new(type_id, file_id, priority))));
+                        }
+                        OPCODE_LOGGED_IN | OPCODE_LOGGED_OUT => {
+                            src.advance(3);
+                            self.logged_in = opcode == OPCODE_LOGGED_IN;
+                        }
+                        OPCODE_ENCRYPTION => {
+                            self.encryption_key = src.get_u8();
+                            src.advance(2);
+                        }
+                        OPCODE_CONNECTION_INITIALISED => {
+                            src.advance(3);
+                            self.initialised = true;
+                            return Ok(Some(GameMessage::Initialised));
+                        }
+                        OPCODE_CONNECTION_CLOSED => {
+                            src.advance(3);
+                            self.initialised = false;
+                            self.state = GameState::Closed;
+                            return Ok(Some(GameMessage::Close));
                         }
                         _ => {
                             src.advance(3);
@@ -117,6 +166,9 @@ impl Decoder for GameDecoder {
                     };
                 }
             }
+            GameState::Closed => {
+                src.clear();
+            }
         }
 
         Ok(None)
@@ -131,42 +183,21 @@ impl Encoder<GameMessage> for GameDecoder {
             GameMessage::UpdateStatus { status_id } => {
                 dst.put_u8(status_id);
             }
-            GameMessage::FileResponse { type_id, file_id, priority, mut container } => {
-                dst.put_u8(type_id);
-                dst.put_u16(file_id);
+            GameMessage::FileResponse { priority, data } => {
+                let start = dst.len();
+                dst.put_slice(&data);
 
-                let mut compression = container.get_u8();
                 if !priority {
-                    compression |= 0x80;
-                }
-
-                dst.put_u8(compression);
-
-                let mut bytes = container.remaining();
-                if bytes > 508 {
-                    bytes = 508;
+                    dst[start + 3] |= 0x80;
                 }
 
-                let mut buffer = vec![0; bytes];
-                container.read_exact(&mut buffer)?;
-
-                dst.put(buffer.as_slice());
-
-                loop {
-                    let mut bytes = container.remaining();
-                    if bytes == 0 {
-                        break;
-                    } else if bytes > 511 {
-                        bytes = 511;
+                if self.encryption_key != 0 {
+                    for byte in &mut dst[start..] {
+                        *byte ^= self.encryption_key;
                     }
-
-                    let mut buffer = vec![0; bytes];
-                    container.read_exact(&mut buffer)?;
-
-                    dst.put_u8(0xFF);
-                    dst.put(buffer.as_slice());
                 }
             }
+            GameMessage::FileRequest(_) | GameMessage::Initialised | GameMessage::Close => {}
         }
 
         Ok(())
@@ -175,26 +206,235 @@ impl Encoder<GameMessage> for GameDecoder {
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let server = Arc::new(GameServer::new()?);
+    if std::env::args().nth(1).as_deref() == Some("keygen") {
+        return generate_rsa_key();
+    }
+
+    let warm_up = std::env::args().any(|arg| arg == "--warm-up");
+    let revisions = parse_revisions()?;
+    let rsa_key = if Path::new(RSA_KEY_PATH).exists() { Some(RsaKey::load(RSA_KEY_PATH)?) } else { None };
+    let server = Arc::new(GameServer::new(CACHE_PATH, rsa_key, warm_up)?);
+    tokio::spawn(Arc::clone(&server).watch());
+
+    #[cfg(unix)]
+    tokio::spawn(Arc::clone(&server).watch_signal());
+
     let addr = "127.0.0.1:43594".to_string().parse::<SocketAddr>().unwrap();
     let listener = TcpListener::bind(&addr).await?;
     println!("Listening for connections on: {}", addr);
 
     loop {
         let (stream, _) = listener.accept().await?;
-        let framed = Framed::new(stream, GameDecoder::new(Arc::clone(&server)));
+        let framed = Framed::new(stream, GameDecoder::with_revisions(Arc::clone(&revisions)));
 
-        tokio::spawn(handle_client(framed));
+        tokio::spawn(handle_client(framed, server.service()));
     }
 }
 
-async fn handle_client(mut framed: Framed<TcpStream, GameDecoder>) -> io::Result<()> {
-    while let Some(message) = framed.next().await {
-        match message {
-            Ok(message) => framed.send(message).await?,
-            Err(e) => return Err(e)
+fn parse_revisions() -> io::Result<Arc<[u32]>> {
+    let revisions = std::env::args()
+        .filter_map(|arg| arg.strip_prefix("--revision=").map(str::to_string))
+        .map(|revision| revision.parse::<u32>().map_err(|e| Error::new(ErrorKind::InvalidInput, e)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(if revisions.is_empty() { Arc::new(REVISIONS) } else { revisions.into() })
+}
+
+fn generate_rsa_key() -> io::Result<()> {
+    if Path::new(RSA_KEY_PATH).exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("RSA key already exists: {}", RSA_KEY_PATH)));
+    }
+
+    let bits = match std::env::args().nth(2) {
+        Some(bits) => bits.parse::<u64>().map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        None => RSA_KEY_BITS,
+    };
+
+    let key = RsaKey::generate(bits)?;
+    key.save(RSA_KEY_PATH)?;
+
+    println!("Generated {} bit RSA key: {}", bits, RSA_KEY_PATH);
+    println!("modulus={}", key.modulus());
+    println!("public_exponent={}", key.public_exponent());
+    Ok(())
+}
+
+async fn handle_client<S: Store + Send + Sync + 'static>(mut framed: Framed<TcpStream, GameDecoder>, service: Arc<UpdateService<S>>) -> io::Result<()> {
+    let mut scheduler = Scheduler::new();
+    let (requests, mut responses) = service.start();
+    let mut pending = false;
+
+    loop {
+        scheduler.set_logged_in(framed.codec().is_logged_in());
+        let ready = if framed.codec().is_initialised() { scheduler.next_ready() } else { None };
+
+        tokio::select! {
+            biased;
+            message = framed.next() => match message {
+                Some(Ok(GameMessage::FileRequest(request))) => scheduler.push(request)?,
+                Some(Ok(GameMessage::Initialised)) => {}
+                Some(Ok(GameMessage::Close)) | None => break,
+                Some(Ok(message)) => framed.send(message).await?,
+                Some(Err(e)) => return Err(e),
+            },
+            response = responses.recv(), if pending => {
+                pending = false;
+                match response {
+                    Some(Ok(response)) => framed.send(response).await?,
+                    Some(Err(e)) => return Err(e),
+                    None => break,
+                }
+            }
+            _ = time::sleep_until(ready.map_or_else(Instant::now, Instant::from_std)), if ready.is_some() && !pending => {
+                if let Some(request) = scheduler.pop() {
+                    if requests.send((request, framed.codec().revision())).await.is_err() {
+                        break;
+                    }
+
+                    pending = true;
+                }
+            }
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(decoder: &mut GameDecoder, version: u32) -> u8 {
+        let mut src = BytesMut::new();
+        src.put_u8(HANDSHAKE_UPDATE);
+        src.put_u32(version);
+
+        match decoder.decode(&mut src).unwrap() {
+            Some(GameMessage::UpdateStatus { status_id }) => status_id,
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_configured_revisions() {
+        let mut decoder = GameDecoder::new();
+        assert_eq!(handshake(&mut decoder, VERSION), STATUS_OK);
+        assert_eq!(decoder.revision(), VERSION);
+        assert_eq!(handshake(&mut GameDecoder::new(), WHIRLPOOL_REVISION), STATUS_OUT_OF_DATE);
+
+        let revisions: Arc<[u32]> = Arc::new([VERSION, WHIRLPOOL_REVISION]);
+        for version in [VERSION, WHIRLPOOL_REVISION] {
+            let mut decoder = GameDecoder::with_revisions(Arc::clone(&revisions));
+            assert_eq!(handshake(&mut decoder, version), STATUS_OK);
+            assert_eq!(decoder.revision(), version);
+        }
+
+        assert_eq!(handshake(&mut GameDecoder::with_revisions(revisions), 531), STATUS_OUT_OF_DATE);
+    }
+
+    fn drain(decoder: &mut GameDecoder, src: &mut BytesMut) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Some(message) = decoder.decode(src).unwrap() {
+            messages.push(format!("{:?}", message));
+        }
+
+        messages
+    }
+
+    #[test]
+    fn handshake_split_at_every_byte() {
+        let mut bytes = vec![HANDSHAKE_UPDATE];
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+
+        for split in 0..=bytes.len() {
+            let mut decoder = GameDecoder::new();
+            let mut src = BytesMut::from(&bytes[..split]);
+            if split < bytes.len() {
+                assert!(decoder.decode(&mut src).unwrap().is_none());
+                assert_eq!(&src[..], &bytes[..split]);
+                src.extend_from_slice(&bytes[split..]);
+            }
+
+            assert!(matches!(decoder.decode(&mut src).unwrap(), Some(GameMessage::UpdateStatus { status_id: STATUS_OK })));
+            assert!(src.is_empty());
+        }
+    }
+
+    #[test]
+    fn opcodes_one_byte_at_a_time() {
+        for opcode in 0..=7 {
+            let mut decoder = GameDecoder::new();
+            handshake(&mut decoder, VERSION);
+
+            let bytes = [opcode, 5, 0x12, 0x34];
+            let mut src = BytesMut::new();
+            for (i, byte) in bytes.iter().enumerate() {
+                src.put_u8(*byte);
+                let message = decoder.decode(&mut src).unwrap();
+                if i < bytes.len() - 1 {
+                    assert!(message.is_none());
+                    assert_eq!(&src[..], &bytes[..=i]);
+                    continue;
+                }
+
+                assert!(src.is_empty(), "opcode {}", opcode);
+                match (opcode, message) {
+                    (OPCODE_PREFETCH | OPCODE_URGENT, Some(GameMessage::FileRequest(request))) => {
+                        assert_eq!(request, FileRequest::new(5, 0x1234, opcode == OPCODE_URGENT));
+                    }
+                    (OPCODE_CONNECTION_INITIALISED, Some(GameMessage::Initialised)) => {}
+                    (OPCODE_CONNECTION_CLOSED, Some(GameMessage::Close)) => {}
+                    (OPCODE_LOGGED_IN | OPCODE_LOGGED_OUT | OPCODE_ENCRYPTION | 5, None) => {}
+                    (opcode, message) => panic!("opcode {}: unexpected message {:?}", opcode, message),
+                }
+            }
+
+            assert_eq!(decoder.is_logged_in(), opcode == OPCODE_LOGGED_IN);
+            assert_eq!(decoder.is_initialised(), opcode == OPCODE_CONNECTION_INITIALISED);
+            assert_eq!(decoder.encryption_key, if opcode == OPCODE_ENCRYPTION { 5 } else { 0 });
+            assert_eq!(matches!(decoder.state, GameState::Closed), opcode == OPCODE_CONNECTION_CLOSED);
+        }
+    }
+
+    #[test]
+    fn mixed_stream_cut_at_random_offsets() {
+        let mut seed = 0x2545_f491_u64;
+        let mut next = move |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+
+        let mut stream = vec![HANDSHAKE_UPDATE];
+        stream.extend_from_slice(&VERSION.to_be_bytes());
+        for _ in 0..200 {
+            let opcode = [OPCODE_PREFETCH, OPCODE_URGENT, OPCODE_LOGGED_IN, OPCODE_LOGGED_OUT, OPCODE_ENCRYPTION, 5, OPCODE_CONNECTION_INITIALISED][next(7)];
+            stream.extend_from_slice(&[opcode, next(256) as u8, next(256) as u8, next(256) as u8]);
+        }
+        stream.extend_from_slice(&[OPCODE_CONNECTION_CLOSED, 0, 0, 0]);
+
+        let mut decoder = GameDecoder::new();
+        let expected = drain(&mut decoder, &mut BytesMut::from(&stream[..]));
+        assert!(expected.len() > 2);
+
+        for _ in 0..100 {
+            let mut decoder = GameDecoder::new();
+            let mut src = BytesMut::new();
+            let mut messages = Vec::new();
+            let mut fed = 0;
+
+            while fed < stream.len() {
+                let length = 1 + next(16).min(stream.len() - fed - 1);
+                src.extend_from_slice(&stream[fed..fed + length]);
+                fed += length;
+
+                messages.extend(drain(&mut decoder, &mut src));
+                let consumed = fed - src.len();
+                assert_eq!(&src[..], &stream[consumed..fed]);
+                assert!(consumed == 0 || (consumed - 5).is_multiple_of(REQUEST_SIZE), "consumed {} bytes", consumed);
+            }
+
+            assert_eq!(messages, expected);
+            assert!(src.is_empty());
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::io::{self, Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+const MAX_URGENT_REQUESTS: usize = 20;
+const MAX_PREFETCH_REQUESTS: usize = 20;
+const LOGGED_IN_PREFETCH_DELAY: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileRequest {
+    type_id: u8,
+    file_id: u16,
+    priority: bool,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    urgent: VecDeque<FileRequest>,
+    prefetch: VecDeque<FileRequest>,
+    logged_in: bool,
+    last_prefetch: Option<Instant>,
+}
+
+impl FileRequest {
+    pub fn new(type_id: u8, file_id: u16, priority: bool) -> Self {
+        FileRequest { type_id, file_id, priority }
+    }
+
+    pub fn type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    pub fn file_id(&self) -> u16 {
+        self.file_id
+    }
+
+    pub fn priority(&self) -> bool {
+        self.priority
+    }
+
+    fn is_same_file(&self, other: &FileRequest) -> bool {
+        self.type_id == other.type_id && self.file_id == other.file_id
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler { urgent: VecDeque::new(), prefetch: VecDeque::new(), logged_in: false, last_prefetch: None }
+    }
+
+    pub fn push(&mut self, request: FileRequest) -> io::Result<()> {
+        if self.urgent.iter().any(|r| r.is_same_file(&request)) {
+            return Ok(());
+        }
+
+        if request.priority {
+            if let Some(index) = self.prefetch.iter().position(|r| r.is_same_file(&request)) {
+                self.prefetch.remove(index);
+            }
+
+            if self.urgent.len() >= MAX_URGENT_REQUESTS {
+                return Err(Error::new(ErrorKind::InvalidData, "Too many urgent requests"));
+            }
+
+            self.urgent.push_back(request);
+        } else {
+            if self.prefetch.iter().any(|r| r.is_same_file(&request)) {
+                return Ok(());
+            }
+
+            if self.prefetch.len() >= MAX_PREFETCH_REQUESTS {
+                return Err(Error::new(ErrorKind::InvalidData, "Too many prefetch requests"));
+            }
+
+            self.prefetch.push_back(request);
+        }
+
+        Ok(())
+    }
+
+    pub fn next_ready(&self) -> Option<Instant> {
+        if !self.urgent.is_empty() {
+            return Some(Instant::now());
+        }
+
+        if self.prefetch.is_empty() {
+            return None;
+        }
+
+        match self.last_prefetch {
+            Some(last) if self.logged_in => Some(last + LOGGED_IN_PREFETCH_DELAY),
+            _ => Some(Instant::now()),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<FileRequest> {
+        if let Some(request) = self.urgent.pop_front() {
+            return Some(request);
+        }
+
+        let request = self.prefetch.pop_front()?;
+        self.last_prefetch = Some(Instant::now());
+        Some(request)
+    }
+
+    pub fn set_logged_in(&mut self, logged_in: bool) {
+        self.logged_in = logged_in;
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
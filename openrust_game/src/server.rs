@@ -0,0 +1,120 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use openrust_fs::cache::Cache;
+use openrust_fs::filestore::FileStore;
+use openrust_fs::rsa::RsaKey;
+use crate::update::UpdateService;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct GameServer {
+    root: PathBuf,
+    rsa_key: Option<Arc<RsaKey>>,
+    warm_up: bool,
+    service: RwLock<Arc<UpdateService>>,
+}
+
+impl GameServer {
+    pub fn new<P: AsRef<Path>>(root: P, rsa_key: Option<RsaKey>, warm_up: bool) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let rsa_key = rsa_key.map(Arc::new);
+        let service = Self::open_service(&root, &rsa_key, warm_up)?;
+
+        Ok(Self { root, rsa_key, warm_up, service: RwLock::new(service) })
+    }
+
+    fn open_service(root: &Path, rsa_key: &Option<Arc<RsaKey>>, warm_up: bool) -> io::Result<Arc<UpdateService>> {
+        let cache = Cache::new(FileStore::open(root)?);
+        cache.checksum_table()?;
+
+        let service = UpdateService::new(Arc::new(cache), rsa_key.clone());
+        if warm_up {
+            service.warm_up()?;
+            println!("Warmed up {} bytes of update responses", service.response_stats().size());
+        }
+
+        Ok(Arc::new(service))
+    }
+
+    pub fn service(&self) -> Arc<UpdateService> {
+        Arc::clone(&self.service.read().expect("Failed to acquire lock"))
+    }
+
+    pub fn reload(&self) -> io::Result<()> {
+        let service = Self::open_service(&self.root, &self.rsa_key, self.warm_up)?;
+        *self.service.write().expect("Failed to acquire lock") = service;
+
+        println!("Reloaded cache from: {}", self.root.display());
+        Ok(())
+    }
+
+    pub async fn watch(self: Arc<Self>) {
+        let mut last = Self::last_modified(&self.root);
+        let mut pending = None;
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let modified = Self::last_modified(&self.root);
+            if modified == last {
+                pending = None;
+                continue;
+            }
+
+            if pending != Some(modified) {
+                pending = Some(modified);
+                continue;
+            }
+
+            if self.reload_logged().await {
+                last = modified;
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub async fn watch_signal(self: Arc<Self>) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            self.reload_logged().await;
+        }
+
+        Ok(())
+    }
+
+    async fn reload_logged(self: &Arc<Self>) -> bool {
+        let server = Arc::clone(self);
+        let result = match tokio::task::spawn_blocking(move || server.reload()).await {
+            Ok(result) => result,
+            Err(e) => Err(io::Error::other(e)),
+        };
+
+        if let Err(e) = &result {
+            println!("Failed to reload cache, keeping the current one: {}", e);
+        }
+
+        result.is_ok()
+    }
+
+    fn last_modified(root: &Path) -> Option<(SystemTime, u64)> {
+        let mut latest = None;
+        let mut size = 0;
+
+        for entry in fs::read_dir(root).ok()? {
+            let metadata = entry.ok()?.metadata().ok()?;
+            let modified = metadata.modified().ok()?;
+
+            size += metadata.len();
+            latest = latest.max(Some(modified));
+        }
+
+        latest.map(|modified| (modified, size))
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Mutex};
+use bytes::{BufMut, BytesMut};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task;
+use openrust_fs::cache::Cache;
+use openrust_fs::container::{self, Container};
+use openrust_fs::filestore::FileStore;
+use openrust_fs::lru::{LruCache, LruStats};
+use openrust_fs::rsa::RsaKey;
+use openrust_fs::store::Store;
+use crate::scheduler::FileRequest;
+use crate::{GameMessage, WHIRLPOOL_REVISION};
+
+const DEFAULT_RESPONSE_CAPACITY: usize = 64 * 1024 * 1024;
+const BLOCK_SIZE: usize = 512;
+
+pub type UpdateResponse = io::Result<GameMessage>;
+type EncodedResponse = Arc<Vec<u8>>;
+
+#[derive(Debug)]
+pub struct UpdateService<S = FileStore> {
+    cache: Arc<Cache<S>>,
+    rsa_key: Option<Arc<RsaKey>>,
+    responses: Mutex<LruCache<(u8, u16), EncodedResponse>>,
+    checksum_tables: Mutex<[Option<EncodedResponse>; 2]>,
+}
+
+impl<S: Store + Send + Sync + 'static> UpdateService<S> {
+    pub fn new(cache: Arc<Cache<S>>, rsa_key: Option<Arc<RsaKey>>) -> Self {
+        Self::with_capacity(cache, rsa_key, DEFAULT_RESPONSE_CAPACITY)
+    }
+
+    pub fn with_capacity(cache: Arc<Cache<S>>, rsa_key: Option<Arc<RsaKey>>, capacity: usize) -> Self {
+        Self {
+            cache,
+            rsa_key,
+            responses: Mutex::new(LruCache::new(capacity)),
+            checksum_tables: Mutex::new([None, None]),
+        }
+    }
+
+    pub fn warm_up(&self) -> io::Result<()> {
+        for type_id in 0..self.cache.get_type_count() {
+            self.encode_file(255, type_id as u16)?;
+        }
+
+        self.encode_checksum_table(false)?;
+        self.encode_checksum_table(true)?;
+        Ok(())
+    }
+
+    pub fn response_stats(&self) -> LruStats {
+        self.responses.lock().expect("Failed to acquire lock").stats()
+    }
+
+    pub fn start(self: Arc<Self>) -> (Sender<(FileRequest, u32)>, Receiver<UpdateResponse>) {
+        let (request_sender, mut requests) = mpsc::channel::<(FileRequest, u32)>(1);
+        let (response_sender, responses) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            while let Some((request, revision)) = requests.recv().await {
+                let service = Arc::clone(&self);
+                let response = match task::spawn_blocking(move || service.resolve(request, revision)).await {
+                    Ok(response) => response,
+                    Err(e) => Err(io::Error::other(e)),
+                };
+
+                if response_sender.send(response).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        (request_sender, responses)
+    }
+
+    fn resolve(&self, request: FileRequest, revision: u32) -> UpdateResponse {
+        let (type_id, file_id, priority) = (request.type_id(), request.file_id(), request.priority());
+        let data = if type_id == 255 && file_id == 255 {
+            self.encode_checksum_table(revision >= WHIRLPOOL_REVISION)?
+        } else {
+            let data = match self.encode_file(type_id, file_id) {
+                Ok(Some(data)) => Ok(data),
+                Ok(None) => Err(io::Error::new(ErrorKind::NotFound, "empty group")),
+                Err(e) => Err(e),
+            };
+
+            data.inspect_err(|e| {
+                let reason = if e.kind() == ErrorKind::NotFound { "missing" } else { "unreadable" };
+                println!("Closing connection, file {} in index {} is {}: {}", file_id, type_id, reason, e);
+            })?
+        };
+
+        Ok(GameMessage::FileResponse { priority, data })
+    }
+
+    fn encode_file(&self, type_id: u8, file_id: u16) -> io::Result<Option<EncodedResponse>> {
+        let key = (type_id, file_id);
+        if let Some(data) = self.responses.lock().expect("Failed to acquire lock").get(&key) {
+            return Ok(Some(Arc::clone(data)));
+        }
+
+        let mut container = self.cache.store().read(type_id as usize, file_id as usize)?.into_inner();
+        if type_id != 255 && container.len() >= 2 {
+            let len = container.len();
+            container.truncate(len - 2);
+        }
+
+        if container.is_empty() {
+            return Ok(None);
+        }
+
+        let data = Arc::new(encode_response(type_id, file_id, &container));
+        self.responses.lock().expect("Failed to acquire lock").insert(key, Arc::clone(&data), data.len());
+
+        Ok(Some(data))
+    }
+
+    fn encode_checksum_table(&self, whirlpool: bool) -> io::Result<EncodedResponse> {
+        let mut checksum_tables = self.checksum_tables.lock().expect("Failed to acquire lock");
+        if let Some(data) = &checksum_tables[whirlpool as usize] {
+            return Ok(Arc::clone(data));
+        }
+
+        let table = self.cache.checksum_table()?;
+        let encoded = match (&self.rsa_key, whirlpool) {
+            (_, false) => table.encode()?,
+            (Some(key), true) => table.encode_impl(true, Some(key.modulus().clone()), key.private_exponent().cloned())?,
+            (None, true) => table.encode_impl(true, None, None)?,
+        };
+
+        let container = Container::new(container::COMPRESSION_NONE, encoded).encode()?.into_inner();
+        let data = Arc::new(encode_response(255, 255, &container));
+        checksum_tables[whirlpool as usize] = Some(Arc::clone(&data));
+
+        Ok(data)
+    }
+}
+
+fn encode_response(type_id: u8, file_id: u16, container: &[u8]) -> Vec<u8> {
+    let mut buf = BytesMut::with_capacity(container.len() + 3 + container.len() / (BLOCK_SIZE - 1) + 1);
+    buf.put_u8(type_id);
+    buf.put_u16(file_id);
+
+    let (head, mut tail) = container.split_at(container.len().min(BLOCK_SIZE - 3));
+    buf.put_slice(head);
+
+    while !tail.is_empty() {
+        let (block, rest) = tail.split_at(tail.len().min(BLOCK_SIZE - 1));
+        buf.put_u8(0xFF);
+        buf.put_slice(block);
+        tail = rest;
+    }
+
+    buf.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use openrust_fs::memorystore::MemoryStore;
+    use super::*;
+
+    #[test]
+    fn serves_any_store() {
+        let mut cache = Cache::create_with(MemoryStore::new(1)).unwrap();
+        let mut container = Container::new(container::COMPRESSION_NONE, Cursor::new(vec![7; 600]));
+        container.set_version(1);
+
+        let mut container = container.encode().unwrap().into_inner();
+        cache.write(0, 1, &container).unwrap();
+        container.truncate(container.len() - 2);
+
+        let service = UpdateService::new(Arc::new(cache), None);
+        let data = match service.resolve(FileRequest::new(0, 1, true), crate::VERSION).unwrap() {
+            GameMessage::FileResponse { priority: true, data } => data,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        assert_eq!(*data, encode_response(0, 1, &container));
+        assert_eq!(service.cache.raw_cache_stats().size(), 0);
+        assert_eq!(data.len(), 3 + container.len() + 1);
+        assert_eq!(data[BLOCK_SIZE], 0xFF);
+    }
+
+    #[test]
+    fn missing_file_is_error() {
+        let service = UpdateService::new(Arc::new(Cache::create_with(MemoryStore::new(1)).unwrap()), None);
+        let e = service.resolve(FileRequest::new(0, 1, true), crate::VERSION).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+    }
+}